@@ -0,0 +1,110 @@
+use std::iter;
+
+use crate::{commands::Primitive, errors::ErrorKind};
+
+// An array created by DIM. String arrays hold fixed-length strings, so the
+// last dimension given to DIM is the length of each string rather than
+// another subscript.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Array {
+    dimensions: Vec<usize>,
+    length: Option<usize>,
+    values: Vec<Primitive>,
+}
+
+impl Array {
+    pub fn numeric(dimensions: Vec<usize>) -> Self {
+        Array {
+            values: vec![Primitive::Int(0); dimensions.iter().product()],
+            length: None,
+            dimensions,
+        }
+    }
+
+    pub fn string(mut dimensions: Vec<usize>) -> Self {
+        let length = dimensions.pop().unwrap_or(1);
+        Array {
+            values: vec![Primitive::String(" ".repeat(length)); dimensions.iter().product()],
+            length: Some(length),
+            dimensions,
+        }
+    }
+
+    // Subscripts start at 1, and every dimension must be given
+    fn offset(&self, subscripts: &[usize]) -> Result<usize, ErrorKind> {
+        if subscripts.len() != self.dimensions.len() {
+            return Err(ErrorKind::SubscriptWrong);
+        }
+
+        subscripts
+            .iter()
+            .zip(&self.dimensions)
+            .try_fold(0, |offset, (&subscript, &dimension)| {
+                match subscript >= 1 && subscript <= dimension {
+                    true => Ok(offset * dimension + subscript - 1),
+                    false => Err(ErrorKind::SubscriptWrong),
+                }
+            })
+    }
+
+    // Splits off the subscript selecting a single character of a string, if
+    // one was given
+    fn character<'a>(&self, subscripts: &'a [usize]) -> (&'a [usize], Option<usize>) {
+        match (self.length, subscripts.split_last()) {
+            (Some(_), Some((character, rest))) if rest.len() == self.dimensions.len() => {
+                (rest, Some(*character))
+            }
+            _ => (subscripts, None),
+        }
+    }
+
+    pub fn get(&self, subscripts: &[usize]) -> Result<Primitive, ErrorKind> {
+        let (subscripts, character) = self.character(subscripts);
+        let value = &self.values[self.offset(subscripts)?];
+
+        match (value, character) {
+            (Primitive::String(value), Some(character)) => match character {
+                0 => Err(ErrorKind::SubscriptWrong),
+                _ => value
+                    .chars()
+                    .nth(character - 1)
+                    .map(|c| Primitive::String(c.to_string()))
+                    .ok_or(ErrorKind::SubscriptWrong),
+            },
+            (value, _) => Ok(value.clone()),
+        }
+    }
+
+    // Strings are padded with spaces or truncated to fit the array
+    pub fn set(&mut self, subscripts: &[usize], value: Primitive) -> Result<(), ErrorKind> {
+        let (subscripts, character) = self.character(subscripts);
+        let offset = self.offset(subscripts)?;
+
+        match (self.length, &mut self.values[offset], value) {
+            (Some(length), Primitive::String(current), Primitive::String(value)) => {
+                *current = match character {
+                    Some(character) if character >= 1 && character <= length => current
+                        .chars()
+                        .enumerate()
+                        .map(|(i, c)| match i == character - 1 {
+                            true => value.chars().next().unwrap_or(' '),
+                            false => c,
+                        })
+                        .collect(),
+                    Some(_) => return Err(ErrorKind::SubscriptWrong),
+                    None => value
+                        .chars()
+                        .chain(iter::repeat(' '))
+                        .take(length)
+                        .collect(),
+                };
+                Ok(())
+            }
+            (None, current, value @ (Primitive::Int(_) | Primitive::Float(_))) => {
+                *current = value;
+                Ok(())
+            }
+            _ => Err(ErrorKind::NonsenseInBasic),
+        }
+    }
+}
@@ -1,299 +1,2859 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, BufRead, Write},
+    iter,
+    ops::Range,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    arrays::Array,
+    blocks::{self, Position},
+    commands::{Command, FunctionDefinition, InputItem, Line, Primitive, PrintOutput},
+    errors::{BlockError, ErrorKind, LoadError, ParseError, RuntimeError},
+    lines::Lines,
+    parsers::{
+        self,
+        expressions::{ExpressionTarget, Function, Operator, Slice, UnaryOperator},
+    },
+};
+
+pub const DEFAULT_MAX_GOSUB_DEPTH: usize = 1024;
+
+// The largest magnitude a Spectrum floating point number can hold
+const MAX_NUMBER: f64 = 1.7014118346046923e38;
+
+// The size of the Spectrum's screen, and of the zones that "," moves between
+const SCREEN_WIDTH: usize = 32;
+const SCREEN_HEIGHT: usize = 22;
+const ZONE_WIDTH: usize = 16;
+
+// A function that calls itself can never stop, so give up once calls are this deep
+const MAX_CALL_DEPTH: usize = 64;
+
+// The most numbers, or characters of a string array, that DIM will make room for
+const MAX_ARRAY_SIZE: usize = 1 << 20;
+
+// Statements typed without a line number are run as if they were on line 0
+const IMMEDIATE_LINE: usize = 0;
+
+// An active FOR loop, and the statement that NEXT returns to just after
+#[derive(Debug, PartialEq, Clone)]
+struct LoopControl {
+    variable: String,
+    limit: Primitive,
+    step: Primitive,
+    line: usize,
+    statement: usize,
+}
+
+// Which flavour of BASIC to accept. The structured dialect adds WHILE/WEND,
+// REPEAT/UNTIL and multi-line IF/ELSE/END IF to Sinclair BASIC.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Dialect {
+    #[default]
+    Sinclair,
+    Structured,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ReportKind {
+    Ok,
+    Stop,
+}
+
+// How a program came to a halt without an error, and the statement it got to,
+// e.g. "0 OK, 30:1" after running off the end
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Report {
+    pub kind: ReportKind,
+    pub line: usize,
+    pub statement: usize,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ReportKind::Ok => write!(f, "0 OK, {}:{}", self.line, self.statement),
+            ReportKind::Stop => write!(f, "9 STOP statement, {}:{}", self.line, self.statement),
+        }
+    }
+}
+
+// The line being run: one of the program's, by its index, or the statements
+// typed in immediate mode
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Cursor {
+    Line(usize),
+    Immediate,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    // Shared with whatever is running, so statements can be borrowed rather
+    // than copied while they run
+    lines: Rc<Lines>,
+    current: Cursor,
+    statement: usize,
+    vars: HashMap<String, Primitive>,
+    arrays: HashMap<String, Array>,
+    loops: Vec<LoopControl>,
+    // The index of the line DATA is being read from
+    data: usize,
+    data_item: usize,
+    returns: Vec<(usize, usize)>,
+    max_gosub_depth: usize,
+    seed: u16,
+    column: usize,
+    // Where CONTINUE picks up from
+    continue_at: Option<(usize, usize)>,
+    // How many FN calls are being evaluated inside one another
+    calls: usize,
+    dialect: Dialect,
+    // The statement at the other end of each block
+    blocks: HashMap<Position, Position>,
+    // Whether `blocks` is up to date with the program's lines
+    blocks_checked: bool,
+    // The statements last run in immediate mode
+    immediate: Option<Rc<[Command]>>,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::new(Lines::default())
+    }
+}
+
+impl Program {
+    pub fn new(lines: Lines) -> Self {
+        Program {
+            vars: HashMap::new(),
+            arrays: HashMap::new(),
+            loops: Vec::new(),
+            data: 0,
+            data_item: 0,
+            returns: Vec::new(),
+            max_gosub_depth: DEFAULT_MAX_GOSUB_DEPTH,
+            seed: 0,
+            column: 0,
+            continue_at: None,
+            calls: 0,
+            dialect: Dialect::default(),
+            blocks: HashMap::new(),
+            blocks_checked: false,
+            immediate: None,
+            lines: Rc::new(lines),
+            current: Cursor::Line(0),
+            statement: 0,
+        }
+    }
+
+    // Reads a program in the given dialect, checking that every block is closed
+    // and that a Sinclair program doesn't use any
+    pub fn load(source: &str, dialect: Dialect) -> Result<Self, LoadError> {
+        let mut program = Self::parse(source)
+            .map_err(LoadError::Syntax)?
+            .with_dialect(dialect);
+        if dialect == Dialect::Sinclair {
+            blocks::find_structured(&program.lines).map_err(LoadError::Dialect)?;
+        }
+        program.check_blocks().map_err(LoadError::Block)?;
+        Ok(program)
+    }
+
+    // Blocks are matched up again before the program next runs
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self.blocks_checked = false;
+        self
+    }
+
+    // Matches up the ends of every block. This needs doing again whenever the
+    // program is edited, before it is next run.
+    pub fn check_blocks(&mut self) -> Result<(), BlockError> {
+        if self.dialect == Dialect::Structured {
+            self.blocks = blocks::match_blocks(&self.lines)?;
+        }
+        self.blocks_checked = true;
+        Ok(())
+    }
+
+    // Runs check_blocks if the program has changed since it was last checked,
+    // reporting a mismatched block as nonsense where it was found
+    fn ensure_blocks(&mut self) -> Result<(), RuntimeError> {
+        if self.blocks_checked {
+            return Ok(());
+        }
+        self.check_blocks().map_err(|error| RuntimeError {
+            kind: ErrorKind::NonsenseInBasic,
+            line: error.line,
+            statement: error.statement,
+        })
+    }
+
+    // Adds a line to the program, replacing any line with the same number
+    pub fn insert_line(&mut self, line: Line) {
+        Rc::make_mut(&mut self.lines).insert(line);
+        self.edited();
+    }
+
+    pub fn delete_line(&mut self, line: usize) {
+        Rc::make_mut(&mut self.lines).remove(line);
+        self.edited();
+    }
+
+    // Anything still pointing into the old program has to start again
+    fn edited(&mut self) {
+        self.blocks.clear();
+        self.blocks_checked = false;
+        self.current = Cursor::Line(self.lines.len());
+        self.statement = 0;
+        self.data = 0;
+        self.data_item = 0;
+    }
+
+    // Forgets every variable and array, and what was being run, like CLEAR
+    pub fn clear(&mut self) {
+        self.vars.clear();
+        self.arrays.clear();
+        self.loops.clear();
+        self.returns.clear();
+        self.data = 0;
+        self.data_item = 0;
+    }
+
+    // Reads a Sinclair BASIC program. Every line that doesn't parse is reported,
+    // and nothing is run unless they all do.
+    pub fn parse(source: &str) -> Result<Self, Vec<ParseError>> {
+        let mut lines = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, text) in source.lines().enumerate() {
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            match parsers::commands::parse_all(index + 1, text, parsers::commands::parse_line) {
+                Ok(line) => lines.push(line),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Program::new(lines.into_iter().collect()))
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Limits how deeply GO SUB calls can nest before reporting "Out of memory"
+    pub fn with_max_gosub_depth(mut self, depth: usize) -> Self {
+        self.max_gosub_depth = depth;
+        self
+    }
+
+    // Sets the random number seed, as RANDOMIZE does
+    pub fn with_seed(mut self, seed: u16) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // Writes the program out in line order, one line per row
+    pub fn list(&self, output: &mut dyn Write) -> io::Result<()> {
+        for (line, commands) in self.lines.iter() {
+            let statements: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+            writeln!(output, "{} {}", line, statements.join(": "))?;
+        }
+        Ok(())
+    }
+
+    // GO TO and GO SUB can only go to the program's own lines
+    // Where the next PRINT carries on from, so a report can start on a fresh line
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn jump_to_line(&mut self, line: usize) -> Result<(), ErrorKind> {
+        match self.lines.find_line(line) {
+            Some(index) => {
+                self.current = Cursor::Line(index);
+                self.statement = 0;
+                Ok(())
+            }
+            None => Err(ErrorKind::LineNotFound(line)),
+        }
+    }
+
+    // Statements are counted from 0. Jumping past the last statement on a line
+    // carries on from the start of the next one.
+    fn jump_to_statement(&mut self, line: usize, statement: usize) -> Result<(), ErrorKind> {
+        let cursor = match (&self.immediate, line) {
+            (Some(_), IMMEDIATE_LINE) => Some(Cursor::Immediate),
+            _ => self.lines.find_line(line).map(Cursor::Line),
+        };
+        match cursor {
+            Some(cursor) => {
+                self.current = cursor;
+                self.statement = statement;
+                Ok(())
+            }
+            None => Err(ErrorKind::LineNotFound(line)),
+        }
+    }
+
+    // Immediate mode statements are run on their own, so there's no next line
+    fn skip_rest_of_line(&mut self) {
+        self.current = match self.current {
+            Cursor::Line(index) => Cursor::Line(index + 1),
+            Cursor::Immediate => Cursor::Line(self.lines.len()),
+        };
+        self.statement = 0;
+    }
+
+    pub fn execute(&mut self) -> Result<Report, RuntimeError> {
+        self.execute_with(&mut io::stdin().lock(), &mut io::stdout())
+    }
+
+    // Runs the program with INPUT read from `input` and PRINT written to `output`
+    pub fn execute_with(
+        &mut self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Report, RuntimeError> {
+        self.ensure_blocks()?;
+        self.current = Cursor::Line(0);
+        self.statement = 0;
+        self.loops.clear();
+        self.returns.clear();
+        self.data = 0;
+        self.data_item = 0;
+        self.column = 0;
+
+        self.run(input, output)
+    }
+
+    // Carries on after a STOP, or retries the statement that caused an error
+    pub fn continue_with(
+        &mut self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Report, RuntimeError> {
+        self.ensure_blocks()?;
+        match self.continue_at {
+            Some((line, statement)) => {
+                self.jump_to_statement(line, statement)
+                    .map_err(|kind| RuntimeError {
+                        kind,
+                        line,
+                        statement: statement + 1,
+                    })?;
+            }
+            None => {
+                self.current = Cursor::Line(0);
+                self.statement = 0;
+            }
+        }
+
+        self.run(input, output)
+    }
+
+    // Runs statements typed without a line number. They can use the program's
+    // variables and jump into it, as well as CONTINUE after a STOP.
+    pub fn execute_immediate(
+        &mut self,
+        commands: Vec<Command>,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Report, RuntimeError> {
+        self.ensure_blocks()?;
+        self.immediate = Some(commands.into());
+        self.current = Cursor::Immediate;
+        self.statement = 0;
+        // The report from whatever ran before finished off the line
+        self.column = 0;
+
+        // Looking at a variable after a STOP shouldn't stop the program continuing
+        let continue_at = self.continue_at;
+        let result = self.run(input, output);
+        self.immediate = None;
+        let line = match &result {
+            Ok(report) => report.line,
+            Err(error) => error.line,
+        };
+        if line == IMMEDIATE_LINE {
+            self.continue_at = continue_at;
+        }
+        result
+    }
+
+    fn run(
+        &mut self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Report, RuntimeError> {
+        let lines = Rc::clone(&self.lines);
+        let immediate = self.immediate.clone();
+
+        let mut last = (0, 0);
+        while let Some((line, statement, command)) =
+            self.next_statement(&lines, immediate.as_deref())
+        {
+            last = (line, statement);
+            match self.execute_command(line, statement, command, input, output) {
+                Ok(()) => (),
+                Err(ErrorKind::StopStatement) => {
+                    self.continue_at = Some((line, statement + 1));
+                    return Ok(Report {
+                        kind: ReportKind::Stop,
+                        line,
+                        statement: statement + 1,
+                    });
+                }
+                Err(kind) => {
+                    self.continue_at = Some((line, statement));
+                    return Err(RuntimeError {
+                        kind,
+                        line,
+                        statement: statement + 1,
+                    });
+                }
+            }
+        }
+
+        let (line, statement) = last;
+        self.continue_at = Some((line, statement + 1));
+        Ok(Report {
+            kind: ReportKind::Ok,
+            line,
+            statement: statement + 1,
+        })
+    }
+
+    // Moves on a statement, returning it along with its line number and index.
+    // The statement is borrowed from `lines`, or from `immediate` in immediate
+    // mode, which are held apart from the program so it can run while borrowed.
+    fn next_statement<'a>(
+        &mut self,
+        lines: &'a Lines,
+        immediate: Option<&'a [Command]>,
+    ) -> Option<(usize, usize, &'a Command)> {
+        loop {
+            let (line, commands) = match self.current {
+                Cursor::Line(index) => {
+                    let (line, commands) = lines.get(index)?;
+                    (*line, commands.as_slice())
+                }
+                Cursor::Immediate => (IMMEDIATE_LINE, immediate?),
+            };
+
+            match commands.get(self.statement) {
+                Some(command) => {
+                    let statement = self.statement;
+                    self.statement += 1;
+                    return Some((line, statement, command));
+                }
+                None => self.skip_rest_of_line(),
+            }
+        }
+    }
+
+    fn execute_command(
+        &mut self,
+        line: usize,
+        statement: usize,
+        command: &Command,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        match command {
+            Command::Print(items) => self.print(items, output)?,
+            Command::Input(items) => {
+                for item in items {
+                    match item {
+                        InputItem::Prompt(prompt) => {
+                            write!(output, "{}", prompt)?;
+                            output.flush()?;
+                        }
+                        InputItem::Variable(variable) => {
+                            let value = self.read_input(variable, input, output)?;
+                            self.assign_variable(variable.clone(), value)?;
+                        }
+                    }
+                }
+                // Whoever typed the input finished it by pressing Enter
+                self.column = 0;
+            }
+            Command::GoTo(target) => {
+                let target = self.evaluate_integer(target)?;
+                self.jump_to_line(target)?;
+            }
+            Command::GoSub(target) => {
+                let target = self.evaluate_integer(target)?;
+                if self.returns.len() >= self.max_gosub_depth {
+                    return Err(ErrorKind::OutOfMemory);
+                }
+                // Only remember where to come back to once the target is known to exist
+                self.jump_to_line(target)?;
+                self.returns.push((line, statement));
+            }
+            // A selector that doesn't pick any of the jumps carries on to the next statement
+            Command::On(selector, jumps) => {
+                let selector = self.evaluate_integer(selector)?;
+                let jump = selector.checked_sub(1).and_then(|index| jumps.get(index));
+                if let Some(jump) = jump {
+                    self.execute_command(line, statement, jump, input, output)?;
+                }
+            }
+            Command::Return => match self.returns.pop() {
+                Some((line, statement)) => self.jump_to_statement(line, statement + 1)?,
+                None => return Err(ErrorKind::ReturnWithoutGoSub),
+            },
+            Command::Var((id, expression)) => {
+                let value = self.evaluate(expression)?;
+                self.assign_variable(id.clone(), value)?;
+            }
+            Command::Dim((id, dimensions)) => {
+                let dimensions = self.evaluate_subscripts(dimensions)?;
+                if dimensions.contains(&0) {
+                    return Err(ErrorKind::SubscriptWrong);
+                }
+                let size = dimensions
+                    .iter()
+                    .try_fold(1usize, |size, &dimension| size.checked_mul(dimension));
+                match size {
+                    Some(size) if size <= MAX_ARRAY_SIZE => (),
+                    _ => return Err(ErrorKind::OutOfMemory),
+                }
+
+                let array = match id.ends_with('$') {
+                    true => {
+                        self.vars.remove(id);
+                        Array::string(dimensions)
+                    }
+                    false => Array::numeric(dimensions),
+                };
+                self.arrays.insert(id.clone(), array);
+            }
+            Command::SetElement((id, subscripts, expression)) => {
+                let value = self.evaluate(expression)?;
+                self.assign_element(id, subscripts, value)?;
+            }
+            Command::SetSlice((id, slice, expression)) => {
+                let length = self.string_variable(id)?.chars().count();
+                let (from, to) = self.evaluate_slice(slice, length)?;
+                let value = self.evaluate(expression)?;
+                self.assign_slice(id, from, to, value)?;
+            }
+            Command::Data(_) => (),
+            Command::Randomize(seed) => {
+                let seed = match seed {
+                    Some(seed) => self.evaluate_integer(seed)?,
+                    None => 0,
+                };
+                self.seed = match seed {
+                    // Without a seed the Spectrum uses how long it's been switched
+                    // on, so use the clock instead
+                    0 => SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|time| (time.as_millis() / 20) as u16)
+                        .unwrap_or(0),
+                    seed => seed as u16,
+                };
+            }
+            Command::Read(targets) => {
+                for target in targets {
+                    let value = self.read_data()?;
+                    match target {
+                        ExpressionTarget::Element(id, subscripts) => {
+                            self.assign_element(id, subscripts, value)?
+                        }
+                        ExpressionTarget::Variable(id) => {
+                            self.assign_variable(id.clone(), value)?
+                        }
+                        _ => return Err(ErrorKind::NonsenseInBasic),
+                    }
+                }
+            }
+            Command::Restore(line) => {
+                self.data = match line {
+                    Some(line) => self.lines.find_next_line(*line),
+                    None => 0,
+                };
+                self.data_item = 0;
+            }
+            Command::If(condition, command) => {
+                // A false condition skips everything else on the line, not just the
+                // statement after THEN
+                if is_true(&self.evaluate(condition)?)? {
+                    self.execute_command(line, statement, command, input, output)?;
+                } else {
+                    self.skip_rest_of_line();
+                }
+            }
+            Command::For((variable, start, limit, step)) => {
+                let start = self.evaluate(start)?;
+                let limit = self.evaluate(limit)?;
+                let step = match step {
+                    Some(step) => self.evaluate(step)?,
+                    None => Primitive::Int(1),
+                };
+
+                // Re-entering a loop replaces it, along with any loops nested inside it
+                if let Some(index) = self.loops.iter().position(|l| &l.variable == variable) {
+                    self.loops.truncate(index);
+                }
+
+                self.vars.insert(variable.clone(), start.clone());
+                if loop_finished(&start, &limit, &step)? {
+                    self.skip_to_next(variable)?;
+                } else {
+                    self.loops.push(LoopControl {
+                        variable: variable.clone(),
+                        limit,
+                        step,
+                        line,
+                        statement,
+                    });
+                }
+            }
+            Command::Next(variable) => self.next_iteration(variable)?,
+            Command::Stop => return Err(ErrorKind::StopStatement),
+            Command::Continue => {
+                if let Some((line, statement)) = self.continue_at {
+                    self.jump_to_statement(line, statement)?;
+                }
+            }
+            Command::While(_)
+            | Command::Wend
+            | Command::Repeat
+            | Command::Until(_)
+            | Command::BlockIf(_)
+            | Command::Else
+            | Command::EndIf => self.execute_block(line, statement, command)?,
+            Command::DefFn(_) | Command::Comment(_) => (),
+            Command::None => return Err(ErrorKind::NonsenseInBasic),
+        };
+
+        Ok(())
+    }
+
+    // Jumps between the ends of a block, using the partners found when the program
+    // was loaded
+    fn execute_block(
+        &mut self,
+        line: usize,
+        statement: usize,
+        command: &Command,
+    ) -> Result<(), ErrorKind> {
+        if self.dialect != Dialect::Structured {
+            return Err(ErrorKind::NonsenseInBasic);
+        }
+
+        let partner = self.blocks.get(&(line, statement)).copied();
+        let (line, statement) = match (command, partner) {
+            (Command::Repeat | Command::EndIf, _) => return Ok(()),
+            // A false WHILE or IF skips past its WEND or ELSE, but a false UNTIL
+            // goes back round to the start of its REPEAT
+            (
+                Command::While(condition) | Command::BlockIf(condition) | Command::Until(condition),
+                Some((line, statement)),
+            ) => match is_true(&self.evaluate(condition)?)? {
+                true => return Ok(()),
+                false => (line, statement + 1),
+            },
+            // WEND goes back to re-test its WHILE
+            (Command::Wend, Some(position)) => position,
+            (Command::Else, Some((line, statement))) => (line, statement + 1),
+            _ => return Err(ErrorKind::NonsenseInBasic),
+        };
+        self.jump_to_statement(line, statement)
+    }
+
+    // Prints each item in turn, keeping track of the column on a 32 column screen.
+    // The line only ends if the list doesn't finish with a separator.
+    fn print(&mut self, items: &[PrintOutput], output: &mut dyn Write) -> Result<(), ErrorKind> {
+        for item in items {
+            match item {
+                PrintOutput::Value(value) => self.write_text(value, output)?,
+                PrintOutput::Expression(expression) => {
+                    let value = self.evaluate(expression)?.to_string();
+                    self.write_text(&value, output)?;
+                }
+                PrintOutput::Tab(column) => {
+                    let column = self.evaluate_integer(column)? % SCREEN_WIDTH;
+                    self.move_to_column(column, output)?;
+                }
+                // There are no rows to move between on a stream, so AT only moves
+                // along the line, but the row is still checked
+                PrintOutput::At(row, column) => {
+                    if self.evaluate_integer(row)? >= SCREEN_HEIGHT {
+                        return Err(ErrorKind::OutOfScreen);
+                    }
+                    match self.evaluate_integer(column)? {
+                        column if column < SCREEN_WIDTH => self.move_to_column(column, output)?,
+                        _ => return Err(ErrorKind::IntegerOutOfRange),
+                    }
+                }
+                PrintOutput::Adjacent => (),
+                PrintOutput::NextZone => {
+                    let column = (self.column / ZONE_WIDTH + 1) * ZONE_WIDTH;
+                    self.move_to_column(column % SCREEN_WIDTH, output)?;
+                }
+                PrintOutput::NewLine => self.write_text("\n", output)?,
+            }
+        }
+
+        match items.last() {
+            Some(PrintOutput::Adjacent | PrintOutput::NextZone | PrintOutput::NewLine) => Ok(()),
+            _ => self.write_text("\n", output),
+        }
+    }
+
+    fn write_text(&mut self, text: &str, output: &mut dyn Write) -> Result<(), ErrorKind> {
+        write!(output, "{}", text)?;
+        for character in text.chars() {
+            self.column = match character {
+                '\n' => 0,
+                _ => (self.column + 1) % SCREEN_WIDTH,
+            };
+        }
+        Ok(())
+    }
+
+    // Pads with spaces up to `column`, on the next line if it's already been passed
+    fn move_to_column(&mut self, column: usize, output: &mut dyn Write) -> Result<(), ErrorKind> {
+        if column < self.column {
+            self.write_text("\n", output)?;
+        }
+        let padding = " ".repeat(column - self.column);
+        self.write_text(&padding, output)
+    }
+
+    // Line numbers, print positions and the like are whole numbers from 0 to 65535
+    fn evaluate_integer(&mut self, position: &ExpressionTarget) -> Result<usize, ErrorKind> {
+        let position = match self.evaluate(position)? {
+            Primitive::Int(value) => value as f64,
+            Primitive::Float(value) => value,
+            Primitive::String(_) => return Err(ErrorKind::NonsenseInBasic),
+        };
+        match position.round() {
+            position if (0.0..=65535.0).contains(&position) => Ok(position as usize),
+            _ => Err(ErrorKind::IntegerOutOfRange),
+        }
+    }
+
+    // Reads a line of input for `variable`. String variables take the line as-is,
+    // numeric variables keep asking until they are given a valid number.
+    fn read_input(
+        &mut self,
+        variable: &str,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Primitive, ErrorKind> {
+        loop {
+            let mut buffer = String::new();
+            if input.read_line(&mut buffer)? == 0 {
+                return Err(ErrorKind::StopInInput);
+            }
+            let text = buffer.trim_end_matches(['\r', '\n']);
+
+            if variable.ends_with('$') {
+                return Ok(Primitive::String(text.to_string()));
+            }
+
+            if let Ok(("", expression)) = parsers::expressions::parse_expression(text.trim()) {
+                if let Ok(value @ (Primitive::Int(_) | Primitive::Float(_))) =
+                    self.evaluate(&expression)
+                {
+                    return Ok(value);
+                }
+            }
+
+            writeln!(output, "?")?;
+        }
+    }
+
+    fn next_iteration(&mut self, variable: &str) -> Result<(), ErrorKind> {
+        let index = match self.loops.iter().rposition(|l| l.variable == variable) {
+            Some(index) => index,
+            None => return Err(ErrorKind::NextWithoutFor),
+        };
+        self.loops.truncate(index + 1);
+
+        let current = self.evaluate(&ExpressionTarget::Variable(variable.to_string()))?;
+        let control = &self.loops[index];
+        let value = apply_operator(&Operator::Add, current, control.step.clone())?;
+        let finished = loop_finished(&value, &control.limit, &control.step)?;
+        let (line, statement) = (control.line, control.statement);
+        self.vars.insert(variable.to_string(), value);
+
+        if finished {
+            self.loops.pop();
+            Ok(())
+        } else {
+            self.jump_to_statement(line, statement + 1)
+        }
+    }
+
+    // A loop that is already past its limit never runs, so execution carries
+    // on after its NEXT instead
+    fn skip_to_next(&mut self, variable: &str) -> Result<(), ErrorKind> {
+        let lines = Rc::clone(&self.lines);
+        let immediate = self.immediate.clone();
+        while let Some((_, _, command)) = self.next_statement(&lines, immediate.as_deref()) {
+            if let Command::Next(next) = command {
+                if next == variable {
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(ErrorKind::ForWithoutNext)
+    }
+
+    fn evaluate(&mut self, expression: &ExpressionTarget) -> Result<Primitive, ErrorKind> {
+        match expression {
+            ExpressionTarget::Val(value) => Ok(value.clone()),
+            ExpressionTarget::Variable(name) => match self.vars.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => match self.arrays.get(name) {
+                    Some(array) if name.ends_with('$') => array.get(&[]),
+                    _ => Err(ErrorKind::VariableNotFound),
+                },
+            },
+            ExpressionTarget::Element(name, subscripts) => {
+                let subscripts = self.evaluate_subscripts(subscripts)?;
+                match (self.arrays.get(name), self.vars.get(name), &subscripts[..]) {
+                    (Some(array), _, _) => array.get(&subscripts),
+                    // Without an array of that name, a$(3) is the third character of a$
+                    (None, Some(Primitive::String(text)), &[index]) => {
+                        substring(text, index, index)
+                    }
+                    _ => Err(ErrorKind::VariableNotFound),
+                }
+            }
+            ExpressionTarget::Call(name, arguments) => self.call(name, arguments),
+            ExpressionTarget::Slice(target, slice) => {
+                let text = match self.evaluate(target)? {
+                    Primitive::String(text) => text,
+                    _ => return Err(ErrorKind::NonsenseInBasic),
+                };
+                let (from, to) = self.evaluate_slice(slice, text.chars().count())?;
+                substring(&text, from, to)
+            }
+            ExpressionTarget::Function(function, argument) => {
+                let argument = match argument {
+                    Some(argument) => Some(self.evaluate(argument)?),
+                    None => None,
+                };
+                self.apply_function(function, argument)
+            }
+            ExpressionTarget::Expression(expression) => {
+                let (lhs, operator, rhs) = expression.as_ref();
+                apply_operator(operator, self.evaluate(lhs)?, self.evaluate(rhs)?)
+            }
+            ExpressionTarget::Unary(operator, operand) => {
+                apply_unary_operator(operator, self.evaluate(operand)?)
+            }
+        }
+    }
+
+    // Arguments are given to the function as variables named after its parameters,
+    // hiding any global variables with those names until it returns
+    fn call(&mut self, name: &str, arguments: &[ExpressionTarget]) -> Result<Primitive, ErrorKind> {
+        let (_, parameters, body) = self.find_definition(name)?;
+        if parameters.len() != arguments.len() {
+            return Err(ErrorKind::ParameterError);
+        }
+        if self.calls >= MAX_CALL_DEPTH {
+            return Err(ErrorKind::OutOfMemory);
+        }
+
+        let mut values = Vec::new();
+        for (parameter, argument) in parameters.iter().zip(arguments) {
+            let value = self.evaluate(argument)?;
+            if parameter.ends_with('$') != matches!(value, Primitive::String(_)) {
+                return Err(ErrorKind::ParameterError);
+            }
+            values.push(value);
+        }
+
+        let hidden: Vec<(String, Option<Primitive>)> = parameters
+            .into_iter()
+            .zip(values)
+            .map(|(parameter, value)| {
+                let global = self.vars.insert(parameter.clone(), value);
+                (parameter, global)
+            })
+            .collect();
+
+        self.calls += 1;
+        let result = self.evaluate(&body);
+        self.calls -= 1;
+
+        for (parameter, global) in hidden.into_iter().rev() {
+            match global {
+                Some(global) => self.vars.insert(parameter, global),
+                None => self.vars.remove(&parameter),
+            };
+        }
+
+        match result? {
+            value if name.ends_with('$') != matches!(value, Primitive::String(_)) => {
+                Err(ErrorKind::NonsenseInBasic)
+            }
+            value => Ok(value),
+        }
+    }
+
+    // DEF FN statements are found wherever they are in the program, whether or
+    // not they have been run
+    fn find_definition(&self, name: &str) -> Result<FunctionDefinition, ErrorKind> {
+        self.lines
+            .iter()
+            .flat_map(|(_, commands)| commands)
+            .find_map(|command| match command {
+                Command::DefFn(definition) if definition.0 == name => Some(definition.clone()),
+                _ => None,
+            })
+            .ok_or(ErrorKind::FnWithoutDef)
+    }
+
+    fn assign_variable(&mut self, id: String, value: Primitive) -> Result<(), ErrorKind> {
+        if id.ends_with('$') != matches!(value, Primitive::String(_)) {
+            return Err(ErrorKind::NonsenseInBasic);
+        }
+
+        match self.arrays.get_mut(&id) {
+            // A one dimensional string array can be assigned to as a whole
+            Some(array) if id.ends_with('$') => array.set(&[], value),
+            _ => {
+                self.vars.insert(id, value);
+                Ok(())
+            }
+        }
+    }
+
+    fn assign_element(
+        &mut self,
+        id: &str,
+        subscripts: &[ExpressionTarget],
+        value: Primitive,
+    ) -> Result<(), ErrorKind> {
+        let subscripts = self.evaluate_subscripts(subscripts)?;
+        if let Some(array) = self.arrays.get_mut(id) {
+            return array.set(&subscripts, value);
+        }
+
+        match &subscripts[..] {
+            &[index] if id.ends_with('$') => self.assign_slice(id, index, index, value),
+            _ => Err(ErrorKind::VariableNotFound),
+        }
+    }
+
+    fn assign_slice(
+        &mut self,
+        id: &str,
+        from: usize,
+        to: usize,
+        value: Primitive,
+    ) -> Result<(), ErrorKind> {
+        let Primitive::String(replacement) = value else {
+            return Err(ErrorKind::NonsenseInBasic);
+        };
+        let text = self.string_variable(id)?;
+
+        let mut characters: Vec<char> = text.chars().collect();
+        let range = slice_range(from, to, characters.len())?;
+        let length = range.len();
+        characters.splice(
+            range,
+            replacement.chars().chain(iter::repeat(' ')).take(length),
+        );
+        self.assign_variable(
+            id.to_string(),
+            Primitive::String(characters.into_iter().collect()),
+        )
+    }
+
+    // A string variable, or a one dimensional string array standing in for one
+    fn string_variable(&mut self, id: &str) -> Result<String, ErrorKind> {
+        match self.evaluate(&ExpressionTarget::Variable(id.to_string()))? {
+            Primitive::String(text) => Ok(text),
+            _ => Err(ErrorKind::VariableNotFound),
+        }
+    }
+
+    // Takes the next item from the DATA statements, working through the
+    // program in line order from wherever the data pointer was left
+    // Every DATA statement on a line is read before moving on to the next line
+    fn read_data(&mut self) -> Result<Primitive, ErrorKind> {
+        loop {
+            let Some((_, commands)) = self.lines.get(self.data) else {
+                return Err(ErrorKind::OutOfData);
+            };
+
+            let item = commands
+                .iter()
+                .filter_map(|command| match command {
+                    Command::Data(items) => Some(items),
+                    _ => None,
+                })
+                .flatten()
+                .nth(self.data_item)
+                .cloned();
+            match item {
+                Some(item) => {
+                    self.data_item += 1;
+                    return self.evaluate(&item);
+                }
+                None => {
+                    self.data += 1;
+                    self.data_item = 0;
+                }
+            }
+        }
+    }
+
+    fn apply_function(
+        &mut self,
+        function: &Function,
+        argument: Option<Primitive>,
+    ) -> Result<Primitive, ErrorKind> {
+        let value = match &argument {
+            Some(Primitive::Int(value)) => *value as f64,
+            Some(Primitive::Float(value)) => *value,
+            Some(Primitive::String(text)) => return self.apply_string_function(function, text),
+            None => 0.0,
+        };
+
+        match function {
+            Function::Sin => float(value.sin()),
+            Function::Cos => float(value.cos()),
+            Function::Tan => float(value.tan()),
+            Function::Sqr if value < 0.0 => Err(ErrorKind::InvalidArgument),
+            Function::Sqr => float(value.sqrt()),
+            Function::Exp => float(value.exp()),
+            Function::Ln if value <= 0.0 => Err(ErrorKind::InvalidArgument),
+            Function::Ln => float(value.ln()),
+            Function::Pi => float(std::f64::consts::PI),
+            Function::Rnd => Ok(Primitive::Float(self.random())),
+            // INT always rounds down, so INT -2.5 is -3
+            Function::Int => whole_number(value.floor()),
+            Function::Abs => match argument {
+                Some(Primitive::Int(value)) if value != i64::MIN => Ok(Primitive::Int(value.abs())),
+                _ => float(value.abs()),
+            },
+            Function::Sgn => Ok(Primitive::Int(match value {
+                v if v > 0.0 => 1,
+                v if v < 0.0 => -1,
+                _ => 0,
+            })),
+            Function::Chr => match value.round() {
+                code if (0.0..=255.0).contains(&code) => {
+                    Ok(Primitive::String(char::from(code as u8).to_string()))
+                }
+                _ => Err(ErrorKind::IntegerOutOfRange),
+            },
+            // STR$ gives exactly what PRINT would show
+            Function::Str => Ok(Primitive::String(
+                argument
+                    .map(|number| number.to_string())
+                    .unwrap_or_default(),
+            )),
+            Function::Len | Function::Code | Function::Val | Function::ValStr => {
+                Err(ErrorKind::NonsenseInBasic)
+            }
+        }
+    }
+
+    fn apply_string_function(
+        &mut self,
+        function: &Function,
+        text: &str,
+    ) -> Result<Primitive, ErrorKind> {
+        match function {
+            Function::Len => Ok(Primitive::Int(text.chars().count() as i64)),
+            Function::Code => Ok(Primitive::Int(text.chars().next().map_or(0, |c| c as i64))),
+            Function::Val => match self.evaluate_text(text)? {
+                Primitive::String(_) => Err(ErrorKind::NonsenseInBasic),
+                number => Ok(number),
+            },
+            Function::ValStr => match self.evaluate_text(text)? {
+                Primitive::String(text) => Ok(Primitive::String(text)),
+                _ => Err(ErrorKind::NonsenseInBasic),
+            },
+            _ => Err(ErrorKind::NonsenseInBasic),
+        }
+    }
+
+    // VAL and VAL$ run their argument through the same parser as the program itself,
+    // nesting as deeply as FN calls do before giving up
+    fn evaluate_text(&mut self, text: &str) -> Result<Primitive, ErrorKind> {
+        let expression = match parsers::expressions::parse_expression(text.trim()) {
+            Ok(("", expression)) => expression,
+            _ => return Err(ErrorKind::NonsenseInBasic),
+        };
+        if self.calls >= MAX_CALL_DEPTH {
+            return Err(ErrorKind::OutOfMemory);
+        }
+
+        self.calls += 1;
+        let result = self.evaluate(&expression);
+        self.calls -= 1;
+        result
+    }
+
+    // The Spectrum's own generator, so a given seed produces the same
+    // sequence of numbers as the real machine
+    fn random(&mut self) -> f64 {
+        self.seed = ((75 * (self.seed as u32 + 1)) % 65537 - 1) as u16;
+        self.seed as f64 / 65536.0
+    }
+
+    fn evaluate_subscripts(
+        &mut self,
+        subscripts: &[ExpressionTarget],
+    ) -> Result<Vec<usize>, ErrorKind> {
+        subscripts
+            .iter()
+            .map(|subscript| self.evaluate_subscript(subscript))
+            .collect()
+    }
+
+    fn evaluate_subscript(&mut self, subscript: &ExpressionTarget) -> Result<usize, ErrorKind> {
+        match self.evaluate(subscript)? {
+            Primitive::Int(value) => usize::try_from(value).map_err(|_| ErrorKind::SubscriptWrong),
+            // Fractional subscripts are rounded to the nearest whole number
+            Primitive::Float(value) if value >= -0.5 && value < usize::MAX as f64 => {
+                Ok(value.round() as usize)
+            }
+            Primitive::Float(_) => Err(ErrorKind::SubscriptWrong),
+            Primitive::String(_) => Err(ErrorKind::NonsenseInBasic),
+        }
+    }
+
+    // A missing start is the first character, and a missing end the last
+    fn evaluate_slice(
+        &mut self,
+        (from, to): &Slice,
+        length: usize,
+    ) -> Result<(usize, usize), ErrorKind> {
+        let from = match from {
+            Some(from) => self.evaluate_subscript(from)?,
+            None => 1,
+        };
+        let to = match to {
+            Some(to) => self.evaluate_subscript(to)?,
+            None => length,
+        };
+        Ok((from, to))
+    }
+}
+
+fn apply_operator(
+    operator: &Operator,
+    lhs: Primitive,
+    rhs: Primitive,
+) -> Result<Primitive, ErrorKind> {
+    match (operator, lhs, rhs) {
+        // x AND y is x when y is true, otherwise 0 (or "" for a string x)
+        (Operator::And, lhs, rhs) => match (is_true(&rhs)?, lhs) {
+            (true, lhs) => Ok(lhs),
+            (false, Primitive::String(_)) => Ok(Primitive::String(String::new())),
+            (false, _) => Ok(Primitive::Int(0)),
+        },
+        // x OR y is 1 when y is true, otherwise x
+        (Operator::Or, lhs @ (Primitive::Int(_) | Primitive::Float(_)), rhs) => {
+            match is_true(&rhs)? {
+                true => Ok(Primitive::Int(1)),
+                false => Ok(lhs),
+            }
+        }
+        (operator, Primitive::Int(lhs), Primitive::Int(rhs)) => {
+            apply_int_operator(operator, lhs, rhs)
+        }
+        (operator, Primitive::Float(lhs), Primitive::Int(rhs)) => {
+            apply_float_operator(operator, lhs, rhs as f64)
+        }
+        (operator, Primitive::Int(lhs), Primitive::Float(rhs)) => {
+            apply_float_operator(operator, lhs as f64, rhs)
+        }
+        (operator, Primitive::Float(lhs), Primitive::Float(rhs)) => {
+            apply_float_operator(operator, lhs, rhs)
+        }
+        (operator, Primitive::String(lhs), Primitive::String(rhs)) => {
+            apply_string_operator(operator, &lhs, &rhs)
+        }
+        _ => Err(ErrorKind::NonsenseInBasic),
+    }
+}
+
+// Integer arithmetic stays exact where it can, and falls back to floating
+// point for fractions, negative powers and anything too big for an integer
+fn apply_int_operator(operator: &Operator, lhs: i64, rhs: i64) -> Result<Primitive, ErrorKind> {
+    let result = match operator {
+        Operator::Add => lhs.checked_add(rhs),
+        Operator::Subtract => lhs.checked_sub(rhs),
+        Operator::Multiply => lhs.checked_mul(rhs),
+        Operator::Divide => match lhs.checked_rem(rhs) {
+            Some(0) => lhs.checked_div(rhs),
+            _ => None,
+        },
+        Operator::Power => u32::try_from(rhs)
+            .ok()
+            .and_then(|exponent| lhs.checked_pow(exponent)),
+        _ => return compare(operator, &lhs, &rhs),
+    };
+
+    match result {
+        Some(value) => Ok(Primitive::Int(value)),
+        None => apply_float_operator(operator, lhs as f64, rhs as f64),
+    }
+}
+
+fn apply_float_operator(operator: &Operator, lhs: f64, rhs: f64) -> Result<Primitive, ErrorKind> {
+    let result = match operator {
+        Operator::Add => lhs + rhs,
+        Operator::Subtract => lhs - rhs,
+        Operator::Multiply => lhs * rhs,
+        Operator::Divide if rhs == 0.0 => return Err(ErrorKind::NumberTooBig),
+        Operator::Divide => lhs / rhs,
+        Operator::Power if lhs < 0.0 && rhs.fract() != 0.0 => {
+            return Err(ErrorKind::InvalidArgument)
+        }
+        Operator::Power => lhs.powf(rhs),
+        _ => return compare(operator, &lhs, &rhs),
+    };
+
+    float(result)
+}
+
+// Keeps whole numbers as integers where they fit
+fn whole_number(value: f64) -> Result<Primitive, ErrorKind> {
+    match value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        true => Ok(Primitive::Int(value as i64)),
+        false => float(value),
+    }
+}
+
+fn float(value: f64) -> Result<Primitive, ErrorKind> {
+    match value.is_finite() && value.abs() <= MAX_NUMBER {
+        true => Ok(Primitive::Float(value)),
+        false => Err(ErrorKind::NumberTooBig),
+    }
+}
+
+fn apply_string_operator(
+    operator: &Operator,
+    lhs: &str,
+    rhs: &str,
+) -> Result<Primitive, ErrorKind> {
+    match operator {
+        Operator::Equal
+        | Operator::NotEqual
+        | Operator::LessThan
+        | Operator::GreaterThan
+        | Operator::LessThanOrEqual
+        | Operator::GreaterThanOrEqual => compare(operator, lhs, rhs),
+        Operator::Add => Ok(Primitive::String(format!("{}{}", lhs, rhs))),
+        _ => Err(ErrorKind::NonsenseInBasic),
+    }
+}
+
+// Slices are 1-based and inclusive. One that ends before it starts is empty
+// wherever it is, but otherwise it has to lie within the string.
+fn slice_range(from: usize, to: usize, length: usize) -> Result<Range<usize>, ErrorKind> {
+    match (from, to) {
+        (from, to) if from > to => Ok(0..0),
+        (from, to) if from >= 1 && to <= length => Ok(from - 1..to),
+        _ => Err(ErrorKind::SubscriptWrong),
+    }
+}
+
+fn substring(text: &str, from: usize, to: usize) -> Result<Primitive, ErrorKind> {
+    let range = slice_range(from, to, text.chars().count())?;
+    Ok(Primitive::String(
+        text.chars().skip(range.start).take(range.len()).collect(),
+    ))
+}
+
+// Relational operators produce 1 for true and 0 for false, as on the Spectrum
+fn compare<T: PartialOrd + ?Sized>(
+    operator: &Operator,
+    lhs: &T,
+    rhs: &T,
+) -> Result<Primitive, ErrorKind> {
+    let result = match operator {
+        Operator::Equal => lhs == rhs,
+        Operator::NotEqual => lhs != rhs,
+        Operator::LessThan => lhs < rhs,
+        Operator::GreaterThan => lhs > rhs,
+        Operator::LessThanOrEqual => lhs <= rhs,
+        Operator::GreaterThanOrEqual => lhs >= rhs,
+        _ => return Err(ErrorKind::NonsenseInBasic),
+    };
+
+    Ok(Primitive::Int(result as i64))
+}
+
+fn loop_finished(
+    value: &Primitive,
+    limit: &Primitive,
+    step: &Primitive,
+) -> Result<bool, ErrorKind> {
+    let counting_down = is_true(&apply_operator(
+        &Operator::LessThan,
+        step.clone(),
+        Primitive::Int(0),
+    )?)?;
+    let operator = match counting_down {
+        true => Operator::LessThan,
+        false => Operator::GreaterThan,
+    };
+    is_true(&apply_operator(&operator, value.clone(), limit.clone())?)
+}
+
+fn is_true(value: &Primitive) -> Result<bool, ErrorKind> {
+    match value {
+        Primitive::Int(value) => Ok(*value != 0),
+        Primitive::Float(value) => Ok(*value != 0.0),
+        Primitive::String(_) => Err(ErrorKind::NonsenseInBasic),
+    }
+}
+
+fn apply_unary_operator(
+    operator: &UnaryOperator,
+    operand: Primitive,
+) -> Result<Primitive, ErrorKind> {
+    match (operator, operand) {
+        (UnaryOperator::Minus, Primitive::Int(value)) => match value.checked_neg() {
+            Some(value) => Ok(Primitive::Int(value)),
+            None => float(-(value as f64)),
+        },
+        (UnaryOperator::Minus, Primitive::Float(value)) => Ok(Primitive::Float(-value)),
+        (UnaryOperator::Not, Primitive::Int(value)) => Ok(Primitive::Int((value == 0) as i64)),
+        (UnaryOperator::Not, Primitive::Float(value)) => Ok(Primitive::Int((value == 0.0) as i64)),
+        _ => Err(ErrorKind::NonsenseInBasic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::basic::PrintOutput;
+
+    use super::{
+        Command, Dialect, ErrorKind, Lines, LoadError, Operator, Primitive, Program, Report,
+        ReportKind, RuntimeError, UnaryOperator,
+    };
+
+    use crate::{
+        commands::{InputItem, Line},
+        parsers::{
+            commands::{parse_line, parse_statements},
+            expressions::ExpressionTarget,
+            generic::read_string,
+        },
+    };
+
+    #[test]
+    fn it_parses_a_print_command() {
+        let input = "10 PRINT \"Hello, world\"";
+        let expected = (
+            10,
+            vec![Command::Print(vec![PrintOutput::Value(String::from(
+                "Hello, world",
+            ))])],
+        );
+
+        let (_, result) = parse_line(input).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_reads_a_string() {
+        let input = r#""Hello, \"World\"""#;
+        let (_, output) = read_string(input).unwrap();
+        assert_eq!(r#"Hello, "World""#, output);
+    }
+
+    #[test]
+    fn it_reads_an_empty_string() {
+        let (rest, output) = read_string("\"\": PRINT").unwrap();
+        assert_eq!(output, "");
+        assert_eq!(rest, ": PRINT");
+
+        let mut program = Program::parse("10 LET a$=\"\"").unwrap();
+        program
+            .execute_with(&mut "".as_bytes(), &mut Vec::new())
+            .unwrap();
+        assert_eq!(
+            program.vars.get("a$"),
+            Some(&Primitive::String(String::new()))
+        );
+    }
+
+    #[test]
+    fn it_parses_a_print_command_with_escaped_quotes() {
+        let input = r#"10 PRINT "Hello, \"world\"""#;
+        let expected = (
+            10,
+            vec![Command::Print(vec![PrintOutput::Value(String::from(
+                r#"Hello, "world""#,
+            ))])],
+        );
+
+        let (_, result) = parse_line(input).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_parses_a_goto_command() {
+        let input = "20 GO TO 10";
+        let expected = (20, vec![Command::GoTo(ExpressionTarget::from(10))]);
+        let (_, result) = parse_line(input).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_keeps_lines_in_order() {
+        let mut lines = Lines::default();
+        lines.insert((20, vec![Command::GoTo(ExpressionTarget::from(10))]));
+        lines.insert((
+            10,
+            vec![Command::Print(vec![PrintOutput::Value(String::from(
+                "Hello world",
+            ))])],
+        ));
+        lines.insert((20, vec![Command::Return]));
+        lines.insert((30, vec![Command::Stop]));
+        lines.remove(30);
+
+        let expected: Lines = vec![
+            (
+                10,
+                vec![Command::Print(vec![PrintOutput::Value(String::from(
+                    "Hello world",
+                ))])],
+            ),
+            (20, vec![Command::Return]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn it_finds_a_line_by_line_number() {
+        let lines: Lines = [40, 10, 30, 20]
+            .into_iter()
+            .map(|line| (line, vec![Command::Comment(String::new())]))
+            .collect();
+
+        assert_eq!(lines.find_line(30), Some(2));
+        assert_eq!(lines.find_line(35), None);
+        assert_eq!(lines.find_next_line(35), 3);
+        assert_eq!(lines.find_next_line(50), 4);
+    }
+
+    #[test]
+    fn it_reads_a_program() {
+        let lines = "10 PRINT \"Hello world\"\n20 GO TO 10";
+        let expected_lines: Lines = vec![
+            (
+                10,
+                vec![Command::Print(vec![PrintOutput::Value(String::from(
+                    "Hello world",
+                ))])],
+            ),
+            (20, vec![Command::GoTo(ExpressionTarget::from(10))]),
+        ]
+        .into_iter()
+        .collect();
+        let expected = Program::new(expected_lines);
+        let result = Program::parse(lines).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_sorts_lines_and_keeps_the_last_of_each_number() {
+        let mut program = Program::parse(
+            "30 PRINT \"c\"\n10 PRINT \"a\"\n20 PRINT \"x\"\n20 PRINT \"b\"\n15 GO TO 20",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        program
+            .execute_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn it_parses_an_integer() {
+        let line = "10 LET a=22";
+        let expected: Line = (
+            10,
+            vec![Command::Var((
+                String::from("a"),
+                ExpressionTarget::from(22),
+            ))],
+        );
+        let (_, result) = parse_line(line).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_parses_a_many_char_integer() {
+        let line = "10 LET apple=1";
+        let expected: Line = (
+            10,
+            vec![Command::Var((
+                String::from("apple"),
+                ExpressionTarget::from(1),
+            ))],
+        );
+        let (_, result) = parse_line(line).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_fails_if_integer_var_name_starts_with_number() {
+        let line = "10 LET 0apple=1";
+        let result = parse_line(line);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_parses_a_string_variable() {
+        let line = r#"10 LET a$="Hello world""#;
+        let expected: Line = (
+            10,
+            vec![Command::Var((
+                String::from("a$"),
+                ExpressionTarget::Val(Primitive::String(String::from("Hello world"))),
+            ))],
+        );
+        let (_, result) = parse_line(line).unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn it_fails_a_string_var_with_a_multichar_name() {
+        let line = r#"10 LET asd$="Hello world""#;
+        let result = parse_line(line);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_fails_a_string_var_with_a_numeric_name() {
+        let line = r#"10 LET 0$="Hello world""#;
+        let result = parse_line(line);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_assigns_one_variable_to_another() {
+        let line = "10 LET a=b$";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Var((
+                String::from("a"),
+                ExpressionTarget::Variable(String::from("b$")),
+            ))],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_parses_a_print_command_with_a_variable_name() {
+        let line = "10 PRINT a$";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Print(vec![PrintOutput::Expression(
+                ExpressionTarget::Variable(String::from("a$")),
+            )])],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_parses_a_comment() {
+        let line = "10 REM This is an arbitrary comment";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Comment(String::from(
+                "This is an arbitrary comment",
+            ))],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_parses_an_expression_assignment() {
+        let line = "10 LET total=price*qty";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Var((
+                String::from("total"),
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("price")),
+                    Operator::Multiply,
+                    ExpressionTarget::Variable(String::from("qty")),
+                )),
+            ))],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_parses_a_print_command_with_an_expression() {
+        let line = "10 PRINT a+1";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Print(vec![PrintOutput::Expression(
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("a")),
+                    Operator::Add,
+                    ExpressionTarget::from(1),
+                )),
+            )])],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_evaluates_expressions_against_variables() {
+        let mut program = Program::parse(
+            "10 LET price=4\n20 LET qty=3\n30 LET tax=2\n40 LET total=price*qty+tax",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("total"), Some(&Primitive::Int(14)));
+    }
+
+    #[test]
+    fn it_reports_an_undefined_variable() {
+        let mut program = Program::parse("10 LET a=b+1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::VariableNotFound)
+        );
+    }
+
+    #[test]
+    fn it_evaluates_with_operator_precedence() {
+        let mut program =
+            Program::parse("10 LET a=1+2*3\n20 LET b=-(1+2)*3^2\n30 LET c=2*-a").unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(7)));
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(-27)));
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Int(-14)));
+    }
+
+    #[test]
+    fn it_parses_an_if_command() {
+        let line = "10 IF a<>b THEN PRINT a";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::If(
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("a")),
+                    Operator::NotEqual,
+                    ExpressionTarget::Variable(String::from("b")),
+                )),
+                Box::new(Command::Print(vec![PrintOutput::Expression(
+                    ExpressionTarget::Variable(String::from("a")),
+                )])),
+            )],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_parses_an_if_command_with_a_line_number() {
+        let line = "10 IF a>=1 THEN 50";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::If(
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("a")),
+                    Operator::GreaterThanOrEqual,
+                    ExpressionTarget::from(1),
+                )),
+                Box::new(Command::GoTo(ExpressionTarget::from(50))),
+            )],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_executes_conditional_statements() {
+        let mut program = Program::parse(
+            r#"10 LET a$="Y"
+20 IF a$="Y" THEN LET b=1
+30 IF a$<"N" THEN LET c=1
+40 IF 2>1 THEN 60
+50 LET d=1
+60 REM Skipped line 50"#,
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(1)));
+        assert_eq!(program.vars.get("c"), None);
+        assert_eq!(program.vars.get("d"), None);
+    }
+
+    #[test]
+    fn it_evaluates_boolean_logic() {
+        let mut program = Program::parse(
+            r#"10 LET a=3 AND 1
+20 LET b=3 AND 0
+30 LET c=0 OR 5
+40 LET d=2 OR 0
+50 LET e$="Yes" AND 0
+60 LET f$="Yes" AND a>b
+70 LET g=NOT b
+80 IF a>0 AND f$="Yes" THEN LET h=1"#,
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(3)));
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(0)));
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Int(1)));
+        assert_eq!(program.vars.get("d"), Some(&Primitive::Int(2)));
+        assert_eq!(
+            program.vars.get("e$"),
+            Some(&Primitive::String(String::new()))
+        );
+        assert_eq!(
+            program.vars.get("f$"),
+            Some(&Primitive::String(String::from("Yes")))
+        );
+        assert_eq!(program.vars.get("g"), Some(&Primitive::Int(1)));
+        assert_eq!(program.vars.get("h"), Some(&Primitive::Int(1)));
+    }
+
+    #[test]
+    fn it_parses_a_for_command() {
+        let line = "10 FOR i=1 TO n STEP -2";
+        let (_, result) = parse_line(line).unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::For((
+                String::from("i"),
+                ExpressionTarget::from(1),
+                ExpressionTarget::Variable(String::from("n")),
+                Some(ExpressionTarget::Unary(
+                    UnaryOperator::Minus,
+                    Box::new(ExpressionTarget::from(2)),
+                )),
+            ))],
+        );
+        assert_eq!(result, expected);
+
+        let (_, result) = parse_line("20 NEXT i").unwrap();
+        assert_eq!(result, (20, vec![Command::Next(String::from("i"))]));
+    }
+
+    #[test]
+    fn it_runs_nested_for_loops() {
+        let mut program = Program::parse(
+            "10 LET total=0
+20 FOR i=1 TO 3
+30 FOR j=10 TO 1 STEP -5
+40 LET total=total+i*j
+50 NEXT j
+60 NEXT i",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("total"), Some(&Primitive::Int(90)));
+        assert_eq!(program.vars.get("i"), Some(&Primitive::Int(4)));
+        assert_eq!(program.vars.get("j"), Some(&Primitive::Int(0)));
+    }
+
+    #[test]
+    fn it_skips_a_for_loop_that_starts_past_its_limit() {
+        let mut program = Program::parse(
+            "10 FOR i=5 TO 1
+20 LET a=1
+30 NEXT i
+40 LET b=1",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), None);
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(1)));
+        assert_eq!(program.vars.get("i"), Some(&Primitive::Int(5)));
+    }
+
+    #[test]
+    fn it_reports_next_without_for() {
+        let mut program = Program::parse("10 NEXT i").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NextWithoutFor)
+        );
+    }
+
+    #[test]
+    fn it_parses_go_sub_and_return() {
+        let (_, result) = parse_line("10 GO SUB 100").unwrap();
+        assert_eq!(
+            result,
+            (10, vec![Command::GoSub(ExpressionTarget::from(100))])
+        );
+
+        let (_, result) = parse_line("100 RETURN").unwrap();
+        assert_eq!(result, (100, vec![Command::Return]));
+    }
+
+    #[test]
+    fn it_returns_from_a_subroutine() {
+        let mut program = Program::parse(
+            "10 LET a=1
+20 GO SUB 100
+30 GO SUB 100
+40 GO TO 200
+100 LET a=a*2
+110 RETURN
+200 REM End",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(4)));
+    }
+
+    #[test]
+    fn it_reports_return_without_go_sub() {
+        let mut program = Program::parse("10 RETURN").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::ReturnWithoutGoSub)
+        );
+    }
+
+    #[test]
+    fn it_reports_runaway_recursion() {
+        let mut program = Program::parse("10 GO SUB 10")
+            .unwrap()
+            .with_max_gosub_depth(50);
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::OutOfMemory)
+        );
+        assert_eq!(program.returns.len(), 50);
+    }
+
+    #[test]
+    fn it_does_not_remember_a_go_sub_to_a_missing_line() {
+        for source in ["10 GO SUB 100", "10 ON 1 GO SUB 100"] {
+            let mut program = Program::parse(source).unwrap();
+            assert_eq!(
+                program.execute().map_err(|error| error.kind),
+                Err(ErrorKind::LineNotFound(100))
+            );
+            assert!(program.returns.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_parses_an_input_command() {
+        let (_, result) = parse_line("10 INPUT \"Name? \"; n$, a").unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Input(vec![
+                InputItem::Prompt(String::from("Name? ")),
+                InputItem::Variable(String::from("n$")),
+                InputItem::Variable(String::from("a")),
+            ])],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_reads_input_into_variables() {
+        let mut program = Program::parse(
+            "10 INPUT \"Name? \"; n$
+20 INPUT a
+30 PRINT a*2",
+        )
+        .unwrap();
+        let mut input = "Lewis\nseven\n7\n".as_bytes();
+        let mut output = Vec::new();
+        program.execute_with(&mut input, &mut output).unwrap();
+
+        assert_eq!(
+            program.vars.get("n$"),
+            Some(&Primitive::String(String::from("Lewis")))
+        );
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(7)));
+        assert_eq!(String::from_utf8(output).unwrap(), "Name? ?\n14\n");
+    }
+
+    #[test]
+    fn it_reports_running_out_of_input() {
+        let mut program = Program::parse("10 INPUT a").unwrap();
+        let result = program.execute_with(&mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result.map_err(|error| error.kind),
+            Err(ErrorKind::StopInInput)
+        );
+    }
+
+    #[test]
+    fn it_parses_dim_and_element_assignment() {
+        let (_, result) = parse_line("10 DIM b$(5,10)").unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Dim((
+                String::from("b$"),
+                vec![ExpressionTarget::from(5), ExpressionTarget::from(10)],
+            ))],
+        );
+        assert_eq!(result, expected);
+
+        let (_, result) = parse_line("20 LET a(i)=a(i)+1").unwrap();
+        let expected: Line = (
+            20,
+            vec![Command::SetElement((
+                String::from("a"),
+                vec![ExpressionTarget::Variable(String::from("i"))],
+                ExpressionTarget::from((
+                    ExpressionTarget::Element(
+                        String::from("a"),
+                        vec![ExpressionTarget::Variable(String::from("i"))],
+                    ),
+                    Operator::Add,
+                    ExpressionTarget::from(1),
+                )),
+            ))],
+        );
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_reads_and_writes_numeric_arrays() {
+        let mut program = Program::parse(
+            "10 DIM m(3,4)
+20 LET a=5
+30 FOR i=1 TO 3
+40 LET m(i,4)=i*10
+50 NEXT i
+60 LET a=m(2,4)+m(1,1)",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(20)));
+    }
+
+    #[test]
+    fn it_reports_arrays_too_big_to_fit() {
+        for source in [
+            "10 DIM a(1E18)",
+            "10 DIM a(65535,65535,65535,65535,65535)",
+            "10 DIM b$(2000,2000)",
+        ] {
+            let mut program = Program::parse(source).unwrap();
+            let result = program.execute_with(&mut "".as_bytes(), &mut Vec::new());
+            assert_eq!(
+                result.map_err(|error| error.kind),
+                Err(ErrorKind::OutOfMemory)
+            );
+        }
+    }
+
+    #[test]
+    fn it_pads_and_truncates_string_array_elements() {
+        let mut program = Program::parse(
+            r#"10 DIM b$(2,5)
+20 LET b$(1)="Hi"
+30 LET b$(2)="Goodbye"
+40 LET b$(2,1)="X"
+50 LET x$=b$(1)
+60 LET y$=b$(2)
+70 LET z$=b$(2,2)"#,
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(
+            program.vars.get("x$"),
+            Some(&Primitive::String(String::from("Hi   ")))
+        );
+        assert_eq!(
+            program.vars.get("y$"),
+            Some(&Primitive::String(String::from("Xoodb")))
+        );
+        assert_eq!(
+            program.vars.get("z$"),
+            Some(&Primitive::String(String::from("o")))
+        );
+    }
+
+    #[test]
+    fn it_assigns_to_a_slice_of_a_string_array() {
+        let mut program = Program::parse(
+            r#"10 DIM a$(5)
+20 LET a$(2 TO 3)="xy"
+30 LET a$(4 TO)="z"
+40 LET b$=a$"#,
+        )
+        .unwrap();
+        program
+            .execute_with(&mut "".as_bytes(), &mut Vec::new())
+            .unwrap();
+        assert_eq!(
+            program.vars.get("b$"),
+            Some(&Primitive::String(String::from(" xyz ")))
+        );
+
+        let mut program = Program::parse("10 DIM a$(2,3)\n20 LET a$(1 TO 2)=\"xy\"").unwrap();
+        assert_eq!(
+            program
+                .execute_with(&mut "".as_bytes(), &mut Vec::new())
+                .map_err(|error| error.kind),
+            Err(ErrorKind::SubscriptWrong)
+        );
+    }
+
+    #[test]
+    fn it_reports_an_out_of_range_subscript() {
+        let mut program = Program::parse("10 DIM a(10)\n20 LET a(11)=1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::SubscriptWrong)
+        );
+
+        let mut program = Program::parse("10 DIM a(10)\n20 PRINT a(0)").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::SubscriptWrong)
+        );
+    }
+
+    #[test]
+    fn it_parses_data_read_and_restore() {
+        let (_, result) = parse_line("10 DATA 1,2*3,\"three\"").unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Data(vec![
+                ExpressionTarget::from(1),
+                ExpressionTarget::from((
+                    ExpressionTarget::from(2),
+                    Operator::Multiply,
+                    ExpressionTarget::from(3),
+                )),
+                ExpressionTarget::Val(Primitive::String(String::from("three"))),
+            ])],
+        );
+        assert_eq!(result, expected);
+
+        let (_, result) = parse_line("20 READ a,b(i),c$").unwrap();
+        let expected: Line = (
+            20,
+            vec![Command::Read(vec![
+                ExpressionTarget::Variable(String::from("a")),
+                ExpressionTarget::Element(
+                    String::from("b"),
+                    vec![ExpressionTarget::Variable(String::from("i"))],
+                ),
+                ExpressionTarget::Variable(String::from("c$")),
+            ])],
+        );
+        assert_eq!(result, expected);
 
-use nom::{bytes::complete::tag, multi::separated_list0, IResult};
+        let (_, result) = parse_line("30 RESTORE").unwrap();
+        assert_eq!(result, (30, vec![Command::Restore(None)]));
 
-use crate::{
-    commands::{Command, Primitive, PrintOutput},
-    node::Node,
-    parsers,
-};
+        let (_, result) = parse_line("40 RESTORE 100").unwrap();
+        assert_eq!(result, (40, vec![Command::Restore(Some(100))]));
+    }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Program {
-    nodes: Node,
-    current: Node,
-    vars: HashMap<String, Primitive>,
-}
+    #[test]
+    fn it_reads_data_in_line_order() {
+        let mut program = Program::parse(
+            r#"10 DIM b(3)
+20 FOR i=1 TO 3
+30 READ b(i)
+40 NEXT i
+50 READ c$
+60 RESTORE 110
+70 READ d
+80 DATA 1,2
+90 DATA 3,"four"
+100 GO TO 120
+110 DATA 5
+120 REM End"#,
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(
+            program.arrays.get("b").unwrap().get(&[3]),
+            Ok(Primitive::Int(3))
+        );
+        assert_eq!(
+            program.vars.get("c$"),
+            Some(&Primitive::String(String::from("four")))
+        );
+        assert_eq!(program.vars.get("d"), Some(&Primitive::Int(5)));
+    }
 
-impl Program {
-    pub fn new(node: Node) -> Self {
-        Program {
-            vars: HashMap::new(),
-            nodes: node.clone(),
-            current: node,
-        }
+    #[test]
+    fn it_reports_running_out_of_data() {
+        let mut program = Program::parse("10 READ a,b\n20 DATA 1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::OutOfData)
+        );
     }
 
-    pub fn jump_to_line(&mut self, line: usize) {
-        if let Some(node) = self.nodes.find_line(line) {
-            self.current = node;
-        } else {
-            panic!("Cannot jump to line {}, it does not exist", line);
-        }
+    #[test]
+    fn it_rejects_data_of_the_wrong_type() {
+        let mut program = Program::parse("10 READ a\n20 DATA \"one\"").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NonsenseInBasic)
+        );
     }
 
-    pub fn execute(&mut self) {
-        let mut iter = self.clone();
+    #[test]
+    fn it_promotes_arithmetic_to_floating_point() {
+        let mut program = Program::parse(
+            "10 LET a=6/3
+20 LET b=1/4
+30 LET c=2^-1
+40 LET d=1.5*2
+50 LET e=9223372036854775807+1
+60 LET f=.5+2E-1",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(2)));
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Float(0.25)));
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Float(0.5)));
+        assert_eq!(program.vars.get("d"), Some(&Primitive::Float(3.0)));
+        assert_eq!(
+            program.vars.get("e"),
+            Some(&Primitive::Float(9223372036854775808.0))
+        );
+        assert_eq!(program.vars.get("f"), Some(&Primitive::Float(0.7)));
+    }
 
-        while let Some(node) = iter.next() {
-            if let Node::Link { item, next: _ } = node {
-                match item.1 {
-                    Command::Print(PrintOutput::Value(line)) => println!("{}", line),
-                    Command::Print(PrintOutput::Variable(variable)) => {
-                        println!("{}", self.vars.get(&variable).unwrap())
-                    }
-                    Command::GoTo(line) => iter.jump_to_line(line),
-                    Command::Var((id, Primitive::Assignment(variable))) => {
-                        self.vars
-                            .insert(id, self.vars.get(&variable).unwrap().clone());
-                    }
-                    Command::Var((id, var)) => {
-                        self.vars.insert(id, var);
-                    }
-                    Command::Comment => (),
-                    _ => panic!("Unrecognised command"),
-                }
-            };
-        }
+    #[test]
+    fn it_reports_division_by_zero() {
+        let mut program = Program::parse("10 LET a=1/0").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NumberTooBig)
+        );
+    }
+
+    #[test]
+    fn it_counts_in_fractional_steps() {
+        let mut program = Program::parse(
+            "10 LET n=0
+20 FOR i=0 TO 1 STEP .25
+30 LET n=n+1
+40 NEXT i",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("n"), Some(&Primitive::Int(5)));
     }
 
-    fn read(i: &str) -> IResult<&str, Self> {
-        let (i, lines) = separated_list0(tag("\n"), parsers::commands::parse_line)(i)?;
-        let mut node = Node::None;
+    #[test]
+    fn it_evaluates_math_functions() {
+        let mut program = Program::parse(
+            "10 LET a=SQR 9+7
+20 LET b=INT -2.5
+30 LET c=ABS -3
+40 LET d=SGN (a-20)
+50 LET e=LN EXP 2
+60 LET f=COS 0+SIN 0+TAN 0
+70 LET g=INT (PI*100)",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Float(10.0)));
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(-3)));
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Int(3)));
+        assert_eq!(program.vars.get("d"), Some(&Primitive::Int(-1)));
+        assert_eq!(program.vars.get("e"), Some(&Primitive::Float(2.0)));
+        assert_eq!(program.vars.get("f"), Some(&Primitive::Float(1.0)));
+        assert_eq!(program.vars.get("g"), Some(&Primitive::Int(314)));
+    }
 
-        for line in lines.iter() {
-            node.push(line.clone());
-        }
+    #[test]
+    fn it_reports_invalid_function_arguments() {
+        let mut program = Program::parse("10 LET a=SQR -1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::InvalidArgument)
+        );
 
-        Ok((i, Program::new(node)))
+        let mut program = Program::parse("10 LET a=LN 0").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::InvalidArgument)
+        );
     }
-}
 
-impl From<&str> for Program {
-    fn from(value: &str) -> Self {
-        let (_, program) = Self::read(value).unwrap();
-        program
+    #[test]
+    fn it_generates_the_same_random_numbers_as_a_spectrum() {
+        let mut program = Program::parse("10 LET a=RND\n20 LET b=RND")
+            .unwrap()
+            .with_seed(0);
+        program.execute().unwrap();
+        assert_eq!(
+            program.vars.get("a"),
+            Some(&Primitive::Float(74.0 / 65536.0))
+        );
+        assert_eq!(
+            program.vars.get("b"),
+            Some(&Primitive::Float(5624.0 / 65536.0))
+        );
     }
-}
 
-impl Iterator for Program {
-    type Item = Node;
+    #[test]
+    fn it_reseeds_with_randomize() {
+        let mut program = Program::parse(
+            "10 RANDOMIZE 42
+20 LET a=RND
+30 RANDOMIZE 42
+40 LET b=RND",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), program.vars.get("b"));
+        assert_eq!(
+            program.vars.get("a"),
+            Some(&Primitive::Float(3224.0 / 65536.0))
+        );
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let curr = self.current.clone();
-        match &self.current {
-            Node::Link { item: _, next } => {
-                self.current = *next.clone();
-                Some(curr)
-            }
-            Node::None => None,
-        }
+        let mut program = Program::parse("10 RANDOMIZE SQR 1764: LET c=RND").unwrap();
+        program.execute().unwrap();
+        assert_eq!(
+            program.vars.get("c"),
+            Some(&Primitive::Float(3224.0 / 65536.0))
+        );
+
+        let mut program = Program::parse("10 RANDOMIZE 70000").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::IntegerOutOfRange)
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::basic::PrintOutput;
+    #[test]
+    fn it_evaluates_string_functions() {
+        let mut program = Program::parse(
+            "10 LET a=LEN \"hello\"
+20 LET b$=CHR$ 65
+30 LET c=CODE \"ABC\"
+40 LET d$=STR$ 1.5
+50 LET x=4
+60 LET e=VAL \"x*2+1\"
+70 LET f$=VAL$ \"\\\"word\\\"\"",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(5)));
+        assert_eq!(
+            program.vars.get("b$"),
+            Some(&Primitive::String(String::from("A")))
+        );
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Int(65)));
+        assert_eq!(
+            program.vars.get("d$"),
+            Some(&Primitive::String(String::from("1.5")))
+        );
+        assert_eq!(program.vars.get("e"), Some(&Primitive::Int(9)));
+        assert_eq!(
+            program.vars.get("f$"),
+            Some(&Primitive::String(String::from("word")))
+        );
+    }
 
-    use super::{Command, Node, Primitive, Program};
+    #[test]
+    fn it_rejects_bad_string_function_arguments() {
+        let mut program = Program::parse("10 LET a=VAL \"2+\"").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NonsenseInBasic)
+        );
 
-    use crate::{
-        commands::Line,
-        parsers::{commands::parse_line, generic::read_string},
-    };
+        let mut program = Program::parse("10 LET a=LEN 5").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NonsenseInBasic)
+        );
+
+        let mut program = Program::parse("10 LET a$=CHR$ 256").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::IntegerOutOfRange)
+        );
+    }
 
     #[test]
-    fn it_parses_a_print_command() {
-        let input = "10 PRINT \"Hello, world\"";
-        let expected = (
-            10,
-            Command::Print(PrintOutput::Value(String::from("Hello, world"))),
+    fn it_limits_how_deeply_val_evaluates_itself() {
+        let mut program = Program::parse("10 LET a$=\"VAL a$\": PRINT VAL a$").unwrap();
+        assert_eq!(
+            program
+                .execute_with(&mut "".as_bytes(), &mut Vec::new())
+                .map_err(|error| error.kind),
+            Err(ErrorKind::OutOfMemory)
         );
+    }
 
-        let (_, result) = parse_line(input).unwrap();
-        assert_eq!(expected, result);
+    #[test]
+    fn it_concatenates_and_slices_strings() {
+        let mut program = Program::parse(
+            "10 LET a$=\"abc\"+\"defgh\"
+20 LET b$=a$(2 TO 5)
+30 LET c$=a$( TO 3)
+40 LET d$=a$(6 TO )
+50 LET e$=a$(3)
+60 LET f$=a$(5 TO 2)
+70 LET g$=\"hello\"(2 TO 3)+a$(8)",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        let string = |s: &str| Some(Primitive::String(String::from(s)));
+        assert_eq!(program.vars.get("a$").cloned(), string("abcdefgh"));
+        assert_eq!(program.vars.get("b$").cloned(), string("bcde"));
+        assert_eq!(program.vars.get("c$").cloned(), string("abc"));
+        assert_eq!(program.vars.get("d$").cloned(), string("fgh"));
+        assert_eq!(program.vars.get("e$").cloned(), string("c"));
+        assert_eq!(program.vars.get("f$").cloned(), string(""));
+        assert_eq!(program.vars.get("g$").cloned(), string("elh"));
     }
 
     #[test]
-    fn it_reads_a_string() {
-        let input = r#""Hello, \"World\"""#;
-        let (_, output) = read_string(input).unwrap();
-        assert_eq!(r#"Hello, "World""#, output);
+    fn it_assigns_to_string_slices_in_place() {
+        let mut program = Program::parse(
+            "10 LET a$=\"abcdefgh\"
+20 LET a$(1 TO 3)=\"x\"
+30 LET a$(7 TO )=\"12345\"
+40 LET a$(4)=\"!\"",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(
+            program.vars.get("a$"),
+            Some(&Primitive::String(String::from("x  !ef12")))
+        );
     }
 
     #[test]
-    fn it_parses_a_print_command_with_escaped_quotes() {
-        let input = r#"10 PRINT "Hello, \"world\"""#;
-        let expected = (
-            10,
-            Command::Print(PrintOutput::Value(String::from(r#"Hello, "world""#))),
+    fn it_reports_an_out_of_range_slice() {
+        let mut program = Program::parse("10 LET a$=\"abc\"\n20 LET b$=a$(2 TO 4)").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::SubscriptWrong)
         );
 
-        let (_, result) = parse_line(input).unwrap();
-        assert_eq!(expected, result);
+        let mut program = Program::parse("10 LET a$=\"abc\"\n20 LET a$(0 TO 1)=\"x\"").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::SubscriptWrong)
+        );
     }
 
     #[test]
-    fn it_parses_a_goto_command() {
-        let input = "20 GO TO 10";
-        let expected = (20, Command::GoTo(10));
-        let (_, result) = parse_line(input).unwrap();
-        assert_eq!(expected, result);
+    fn it_parses_a_print_list() {
+        let (_, result) = parse_line("10 PRINT \"x=\";x,TAB 20;AT 1,2;").unwrap();
+        let expected: Line = (
+            10,
+            vec![Command::Print(vec![
+                PrintOutput::Value(String::from("x=")),
+                PrintOutput::Adjacent,
+                PrintOutput::Expression(ExpressionTarget::Variable(String::from("x"))),
+                PrintOutput::NextZone,
+                PrintOutput::Tab(ExpressionTarget::from(20)),
+                PrintOutput::Adjacent,
+                PrintOutput::At(ExpressionTarget::from(1), ExpressionTarget::from(2)),
+                PrintOutput::Adjacent,
+            ])],
+        );
+        assert_eq!(result, expected);
     }
 
     #[test]
-    fn it_can_create_a_linked_list_for_a_program() {
-        let mut node = Node::Link {
-            item: (
-                10,
-                Command::Print(PrintOutput::Value(String::from("Hello world"))),
-            ),
-            next: Box::new(Node::None),
-        };
-        node.push((20, Command::GoTo(10)));
+    fn it_prints_lists_with_separators() {
+        let mut program = Program::parse(
+            "10 LET x=1
+20 PRINT \"x=\";x,\"y=\";2;
+30 PRINT \"!\"
+40 PRINT \"abcdefghijklmnopqrstuvwxyz\",\"z\"
+50 PRINT TAB 4;\"a\";TAB 2;\"b\"'\"c\"
+60 PRINT AT 5,3;\"d\"
+70 PRINT",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        program
+            .execute_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "x=1             y=2!
+abcdefghijklmnopqrstuvwxyz
+z
+    a
+  b
+c
+   d
 
-        let expected = Node::Link {
-            item: (
-                10,
-                Command::Print(PrintOutput::Value(String::from("Hello world"))),
-            ),
-            next: Box::new(Node::Link {
-                item: (20, Command::GoTo(10)),
-                next: Box::new(Node::None),
-            }),
-        };
-        assert_eq!(node, expected);
+"
+        );
     }
 
     #[test]
-    fn it_finds_a_node_by_line_number() {
-        let mut node = Node::Link {
-            item: (
-                10,
-                Command::Print(PrintOutput::Value(String::from("Hello world"))),
-            ),
-            next: Box::new(Node::None),
-        };
-        node.push((
-            20,
-            Command::Print(PrintOutput::Value(String::from("I'm a second line"))),
-        ));
-        node.push((
-            30,
-            Command::Print(PrintOutput::Value(String::from("Still printing..."))),
-        ));
-        node.push((40, Command::GoTo(10)));
+    fn it_reports_printing_off_the_screen() {
+        let mut program = Program::parse("10 PRINT AT 22,0;\"x\"").unwrap();
+        let result = program.execute_with(&mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result.map_err(|error| error.kind),
+            Err(ErrorKind::OutOfScreen)
+        );
 
-        let expected: Option<Node> = Some(Node::Link {
-            item: (
-                30,
-                Command::Print(PrintOutput::Value(String::from("Still printing..."))),
-            ),
-            next: Box::new(Node::Link {
-                item: (40, Command::GoTo(10)),
-                next: Box::new(Node::None),
-            }),
-        });
-        let result = node.find_line(30);
-        assert_eq!(expected, result);
+        let mut program = Program::parse("10 PRINT AT 0,32;\"x\"").unwrap();
+        let result = program.execute_with(&mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result.map_err(|error| error.kind),
+            Err(ErrorKind::IntegerOutOfRange)
+        );
     }
 
     #[test]
-    fn it_reads_a_program() {
-        let lines = "10 PRINT \"Hello world\"\n20 GO TO 10";
-        let expected_node = Node::Link {
-            item: (
-                10,
-                Command::Print(PrintOutput::Value(String::from("Hello world"))),
-            ),
-            next: Box::new(Node::Link {
-                item: (20, Command::GoTo(10)),
-                next: Box::new(Node::None),
-            }),
-        };
-        let expected = Program::new(expected_node);
-        let result = Program::from(lines);
-        assert_eq!(expected, result);
+    fn it_parses_several_statements_on_a_line() {
+        let (_, result) = parse_line("10 LET a=1: PRINT a:GO TO 30").unwrap();
+        let expected: Line = (
+            10,
+            vec![
+                Command::Var((String::from("a"), ExpressionTarget::from(1))),
+                Command::Print(vec![PrintOutput::Expression(ExpressionTarget::Variable(
+                    String::from("a"),
+                ))]),
+                Command::GoTo(ExpressionTarget::from(30)),
+            ],
+        );
+        assert_eq!(result, expected);
     }
 
     #[test]
-    fn it_parses_an_integer() {
-        let line = "10 LET a=22";
-        let expected: Line = (10, Command::Var((String::from("a"), Primitive::Int(22))));
-        let (_, result) = parse_line(line).unwrap();
-        assert_eq!(expected, result);
+    fn it_resumes_within_a_line() {
+        let mut program = Program::parse(
+            "10 GO SUB 100: PRINT \"back\": GO TO 30
+20 PRINT \"skipped\"
+30 FOR i=1 TO 3: PRINT i;: NEXT i: PRINT
+40 IF 0 THEN PRINT \"a\": PRINT \"b\"
+50 IF 1 THEN PRINT \"c\": PRINT \"d\"
+60 FOR j=2 TO 1: PRINT \"e\": NEXT j: PRINT \"f\"
+70 GO TO 200
+100 PRINT \"sub\": RETURN
+200 REM done: PRINT \"g\"",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        program
+            .execute_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "sub\nback\n123\nc\nd\nf\n"
+        );
     }
 
     #[test]
-    fn it_parses_a_many_char_integer() {
-        let line = "10 LET apple=1";
-        let expected: Line = (10, Command::Var((String::from("apple"), Primitive::Int(1))));
-        let (_, result) = parse_line(line).unwrap();
-        assert_eq!(expected, result);
+    fn it_reports_the_line_and_statement_of_an_error() {
+        let mut program = Program::parse("10 LET a=1\n20 PRINT a: PRINT b").unwrap();
+        let error = program
+            .execute_with(&mut "".as_bytes(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            error,
+            RuntimeError {
+                kind: ErrorKind::VariableNotFound,
+                line: 20,
+                statement: 2,
+            }
+        );
+        assert_eq!(error.to_string(), "2 Variable not found, 20:2");
     }
 
     #[test]
-    fn it_fails_if_integer_var_name_starts_with_number() {
-        let line = "10 LET 0apple=1";
-        let result = parse_line(line);
-        assert!(result.is_err());
+    fn it_stops_and_continues() {
+        let mut program = Program::parse(
+            "10 PRINT \"a\"
+20 STOP: PRINT \"b\"
+30 PRINT \"c\"",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        let report = program
+            .execute_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(
+            report,
+            Report {
+                kind: ReportKind::Stop,
+                line: 20,
+                statement: 1,
+            }
+        );
+        assert_eq!(report.to_string(), "9 STOP statement, 20:1");
+
+        let report = program
+            .continue_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(report.to_string(), "0 OK, 30:1");
+        assert_eq!(String::from_utf8(output).unwrap(), "a\nb\nc\n");
     }
 
     #[test]
-    fn it_parses_a_string_variable() {
-        let line = r#"10 LET a$="Hello world""#;
-        let expected: Line = (
-            10,
-            Command::Var((
-                String::from("a$"),
-                Primitive::String(String::from("Hello world")),
-            )),
-        );
-        let (_, result) = parse_line(line).unwrap();
-        assert_eq!(expected, result);
+    fn it_retries_a_failed_statement_on_continue() {
+        let mut program = Program::parse("10 LET b=a*2\n20 PRINT b").unwrap();
+        let error = program.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::VariableNotFound);
+
+        program.vars.insert(String::from("a"), Primitive::Int(2));
+        let report = program
+            .continue_with(&mut "".as_bytes(), &mut Vec::new())
+            .unwrap();
+        assert_eq!(report.kind, ReportKind::Ok);
+        assert_eq!(program.vars.get("b"), Some(&Primitive::Int(4)));
     }
 
     #[test]
-    fn it_fails_a_string_var_with_a_multichar_name() {
-        let line = r#"10 LET asd$="Hello world""#;
-        let result = parse_line(line);
-        assert!(result.is_err());
+    fn it_calls_user_defined_functions() {
+        let mut program = Program::parse(
+            "10 LET x=10
+20 LET a=FN f(2,3)
+30 LET b$=FN s$(\"hi\")
+40 LET c=FN r()
+50 DEF FN f(x,y)=x*x+y
+60 DEF FN s$(a$)=a$+\"!\"
+70 DEF FN r()=x+FN f(1,1)",
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("a"), Some(&Primitive::Int(7)));
+        assert_eq!(
+            program.vars.get("b$"),
+            Some(&Primitive::String(String::from("hi!")))
+        );
+        assert_eq!(program.vars.get("c"), Some(&Primitive::Int(12)));
+        assert_eq!(program.vars.get("x"), Some(&Primitive::Int(10)));
+        assert_eq!(program.vars.get("y"), None);
     }
 
     #[test]
-    fn it_fails_a_string_var_with_a_numeric_name() {
-        let line = r#"10 LET 0$="Hello world""#;
-        let result = parse_line(line);
-        assert!(result.is_err());
+    fn it_reports_bad_function_calls() {
+        let mut program = Program::parse("10 LET a=FN g(1)").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::FnWithoutDef)
+        );
+
+        let mut program = Program::parse("10 DEF FN f(x)=x\n20 LET a=FN f(1,2)").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::ParameterError)
+        );
+
+        let mut program = Program::parse("10 DEF FN f(x)=x\n20 LET a=FN f(\"1\")").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::ParameterError)
+        );
+
+        let mut program = Program::parse("10 DEF FN f(x)=FN f(x)\n20 LET a=FN f(1)").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::OutOfMemory)
+        );
     }
 
     #[test]
-    fn it_assigns_one_variable_to_another() {
-        let line = "10 LET a=b$";
-        let (_, result) = parse_line(line).unwrap();
+    fn it_parses_a_function_definition() {
+        let (_, result) = parse_line("10 DEF FN s$(a$,n)=a$(n)").unwrap();
         let expected: Line = (
             10,
-            Command::Var((String::from("a"), Primitive::Assignment(String::from("b$")))),
+            vec![Command::DefFn((
+                String::from("s$"),
+                vec![String::from("a$"), String::from("n")],
+                ExpressionTarget::Element(
+                    String::from("a$"),
+                    vec![ExpressionTarget::Variable(String::from("n"))],
+                ),
+            ))],
         );
         assert_eq!(result, expected);
     }
 
     #[test]
-    fn it_parses_a_print_command_with_a_variable_name() {
-        let line = "10 PRINT a$";
-        let (_, result) = parse_line(line).unwrap();
+    fn it_runs_structured_blocks() {
+        let mut program = Program::load(
+            "10 LET i=0: LET t=0
+20 WHILE i<5
+30 LET i=i+1
+40 IF i=2 THEN
+50 LET t=t+100
+60 ELSE
+70 LET t=t+i
+80 END IF
+90 WEND
+100 REPEAT: LET i=i-1: UNTIL i=0
+110 WHILE 0: LET t=-1: WEND",
+            Dialect::Structured,
+        )
+        .unwrap();
+        program.execute().unwrap();
+        assert_eq!(program.vars.get("t"), Some(&Primitive::Int(113)));
+        assert_eq!(program.vars.get("i"), Some(&Primitive::Int(0)));
+    }
+
+    #[test]
+    fn it_reports_unbalanced_blocks_when_loading() {
+        let result = Program::load("10 WHILE 1\n20 IF 1 THEN\n30 WEND", Dialect::Structured);
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "WEND without WHILE, 30:1");
+
+        let result = Program::load("10 REPEAT\n20 PRINT 1", Dialect::Structured);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "REPEAT without UNTIL, 10:1"
+        );
+    }
+
+    #[test]
+    fn it_only_runs_structured_blocks_in_the_structured_dialect() {
+        let mut program = Program::parse("10 REPEAT\n20 UNTIL 1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::NonsenseInBasic)
+        );
+    }
+
+    #[test]
+    fn it_rejects_structured_blocks_when_loading_a_sinclair_program() {
+        let result = Program::load("10 PRINT 1\n20 LET a=1: WHILE a", Dialect::Sinclair);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "WHILE needs the structured dialect, 20:2"
+        );
+
+        let result = Program::load("10 IF a THEN END IF", Dialect::Sinclair);
+        assert!(matches!(result, Err(LoadError::Dialect(_))));
+    }
+
+    #[test]
+    fn it_matches_blocks_after_switching_dialect() {
+        let mut program = Program::parse("10 LET i=0\n20 WHILE i<3\n30 LET i=i+1\n40 WEND")
+            .unwrap()
+            .with_dialect(Dialect::Structured);
+        program
+            .execute_with(&mut "".as_bytes(), &mut Vec::new())
+            .unwrap();
+        assert_eq!(program.vars.get("i"), Some(&Primitive::Int(3)));
+    }
+
+    #[test]
+    fn it_parses_computed_and_dispatched_jumps() {
+        let (_, result) = parse_line("10 ON k GO TO 100,200").unwrap();
         let expected: Line = (
             10,
-            Command::Print(PrintOutput::Variable(String::from("a$"))),
+            vec![Command::On(
+                ExpressionTarget::Variable(String::from("k")),
+                vec![
+                    Command::GoTo(ExpressionTarget::from(100)),
+                    Command::GoTo(ExpressionTarget::from(200)),
+                ],
+            )],
         );
         assert_eq!(result, expected);
     }
 
     #[test]
-    fn it_parses_a_comment() {
-        let line = "10 REM This is an arbitrary comment";
-        let (_, result) = parse_line(line).unwrap();
-        let expected: Line = (10, Command::Comment);
-        assert_eq!(result, expected);
+    fn it_jumps_to_computed_lines() {
+        let mut program = Program::parse(
+            "10 LET n=2: LET k=3
+20 GO SUB n*100
+30 ON k GO TO 40,50,60
+40 PRINT \"forty\"
+50 PRINT \"fifty\"
+60 ON 0 GO SUB 100: ON k-1 GO SUB 100,200: GO TO n*150
+200 PRINT \"sub\": RETURN
+300 PRINT \"end\"",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        program
+            .execute_with(&mut "".as_bytes(), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "sub\nsub\nend\n");
+    }
+
+    #[test]
+    fn it_reports_a_missing_computed_line() {
+        let mut program = Program::parse("10 LET n=3\n20 GO TO n*10+5\n30 PRINT n").unwrap();
+        let error = program.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::LineNotFound(35));
+        assert_eq!(error.line, 20);
+
+        let mut program = Program::parse("10 GO TO -1").unwrap();
+        assert_eq!(
+            program.execute().map_err(|error| error.kind),
+            Err(ErrorKind::IntegerOutOfRange)
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_with_spectrum_codes() {
+        let mut program = Program::parse("10 FOR i=2 TO 1\n20 PRINT i").unwrap();
+        let error = program.execute().unwrap_err();
+        assert_eq!(error.to_string(), "I FOR without NEXT, 10:1");
+
+        let mut program = Program::parse("10 LET a=1: LET b=2\n20 GO TO 100").unwrap();
+        let error = program.execute().unwrap_err();
+        assert_eq!(error.to_string(), "N Line 100 does not exist, 20:1");
+
+        let mut program = Program::parse("10 PRINT 1/0").unwrap();
+        let error = program.execute().unwrap_err();
+        assert_eq!(error.to_string(), "6 Number too big, 10:1");
+    }
+
+    #[test]
+    fn it_reports_every_malformed_line() {
+        let errors =
+            Program::parse("10 PRINT 1\n20 LET a=\n30 PRINT 2\nPRINT 3\n50 GO TO 10").unwrap_err();
+
+        let positions: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|error| (error.line, error.column))
+            .collect();
+        assert_eq!(positions, vec![(2, 10), (4, 1)]);
+        assert_eq!(
+            errors[0].to_string(),
+            "Syntax error at line 2, column 10:\n  20 LET a=\n           ^"
+        );
+    }
+
+    #[test]
+    fn it_refuses_a_partially_parsed_program() {
+        let result = Program::parse("10 PRINT 1\n20 PRINT 2 )\n30 PRINT 3");
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (2, 12));
+
+        let result = Program::load("10 PRINT 1 )", Dialect::Sinclair);
+        assert!(matches!(result, Err(LoadError::Syntax(_))));
+    }
+
+    #[test]
+    fn it_lists_a_program() {
+        let source = "10 LET x=(1+2)*-3^2: REM set up\n20 PRINT \"x=\";x,\"a \\\"b\\\"\";TAB 3;AT 1,2'\n30 IF x>=1 AND NOT y THEN GO TO 10\n40 ON x GO SUB 100,200: DEF FN f(a,b)=a-(b-1)\n50 LET a$(2 TO )=b$( TO 3)+STR$ (x+1): FOR i=1 TO 10 STEP 2: NEXT i";
+        let program = Program::parse(source).unwrap();
+
+        let mut output = Vec::new();
+        program.list(&mut output).unwrap();
+        let listing = String::from_utf8(output).unwrap();
+        assert_eq!(
+            listing,
+            "10 LET x=(1+2)*-3^2: REM set up\n20 PRINT \"x=\";x,\"a \\\"b\\\"\";TAB 3;AT 1,2'\n30 IF x>=1 AND NOT y THEN GO TO 10\n40 ON x GO SUB 100,200: DEF FN f(a,b)=a-(b-1)\n50 LET a$(2 TO )=b$( TO 3)+STR$ (x+1): FOR i=1 TO 10 STEP 2: NEXT i\n"
+        );
+    }
+
+    #[test]
+    fn it_lists_a_program_that_parses_back_the_same() {
+        let source = "10 LET a=1-(2-3)-4/(5*6)^(-1)\n20 PRINT NOT (a=1) OR a$(2)=\"z\";\n30 IF a THEN\n40 WHILE a<>0: INPUT \"n?\";n: READ b(1,a): DATA 1.5,2E-3\n50 WEND: ELSE: END IF: RANDOMIZE 3: RESTORE 40\n60 LET x=1.23456789012+1E30-3.0+123456789+2.5E-9";
+        let program = Program::parse(source).unwrap();
+        let mut output = Vec::new();
+        program.list(&mut output).unwrap();
+        let listing = String::from_utf8(output).unwrap();
+
+        let lines = |source: &str| -> Vec<Line> {
+            source
+                .lines()
+                .map(|line| parse_line(line).unwrap().1)
+                .collect()
+        };
+        assert_eq!(lines(&listing), lines(source));
+    }
+
+    #[test]
+    fn it_inserts_replaces_and_deletes_lines() {
+        let mut program = Program::parse("10 PRINT 1\n30 PRINT 3").unwrap();
+        program.insert_line(parse_line("20 PRINT 2").unwrap().1);
+        program.insert_line(parse_line("5 PRINT 0").unwrap().1);
+        program.insert_line(parse_line("30 PRINT 4").unwrap().1);
+        program.delete_line(10);
+        program.delete_line(15);
+
+        let mut output = Vec::new();
+        program.list(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "5 PRINT 0\n20 PRINT 2\n30 PRINT 4\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_line_0_for_immediate_mode() {
+        let errors = Program::parse("0 PRINT \"zero\"\n10 GO TO 0").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+
+        let mut program = Program::parse("10 GO TO 0").unwrap();
+        let (_, commands) = parse_statements("PRINT 1").unwrap();
+        program
+            .execute_immediate(commands, &mut "".as_bytes(), &mut Vec::new())
+            .unwrap();
+        let result = program.execute_with(&mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result.map_err(|error| error.kind),
+            Err(ErrorKind::LineNotFound(0))
+        );
+    }
+
+    #[test]
+    fn it_does_not_go_to_the_immediate_line() {
+        let mut program = Program::default();
+        let (_, commands) = parse_statements("GO TO 0").unwrap();
+        let result = program.execute_immediate(commands, &mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result.map_err(|error| error.kind),
+            Err(ErrorKind::LineNotFound(0))
+        );
+    }
+
+    #[test]
+    fn it_runs_immediate_statements_against_the_program() {
+        let mut program = Program::parse("100 LET t=t+n: RETURN").unwrap();
+        let (_, commands) = parse_statements("LET t=0: FOR n=1 TO 4: GO SUB 100: NEXT n").unwrap();
+
+        let result = program.execute_immediate(commands, &mut "".as_bytes(), &mut Vec::new());
+        assert_eq!(
+            result,
+            Ok(Report {
+                kind: ReportKind::Ok,
+                line: 0,
+                statement: 4
+            })
+        );
+        assert_eq!(program.vars.get("t"), Some(&Primitive::Int(10)));
+
+        program.clear();
+        assert!(program.vars.is_empty());
     }
 }
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::{
+    commands::Command,
+    errors::{BlockError, DialectError},
+    lines::Lines,
+};
+
+// A statement's line number and its index on that line
+pub type Position = (usize, usize);
+
+// Sinclair programs can't use any of the block statements, even after THEN
+pub fn find_structured(lines: &Lines) -> Result<(), DialectError> {
+    for (line, commands) in lines.iter() {
+        for (statement, command) in commands.iter().enumerate() {
+            if let Some(keyword) = structured_keyword(command) {
+                return Err(DialectError {
+                    keyword,
+                    line: *line,
+                    statement: statement + 1,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn structured_keyword(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::While(_) => Some("WHILE"),
+        Command::Wend => Some("WEND"),
+        Command::Repeat => Some("REPEAT"),
+        Command::Until(_) => Some("UNTIL"),
+        Command::BlockIf(_) => Some("IF ... THEN"),
+        Command::Else => Some("ELSE"),
+        Command::EndIf => Some("END IF"),
+        Command::If(_, command) => structured_keyword(command),
+        _ => None,
+    }
+}
+
+// Pairs up the statements at each end of a block so execution can jump between
+// them: WHILE and WEND both ways, UNTIL back to its REPEAT, IF on to its ELSE or
+// END IF, and ELSE on to its END IF
+pub fn match_blocks(lines: &Lines) -> Result<HashMap<Position, Position>, BlockError> {
+    let mut open: Vec<(&'static str, Position)> = Vec::new();
+    let mut partners = HashMap::new();
+
+    for (line, commands) in lines.iter() {
+        for (statement, command) in commands.iter().enumerate() {
+            let position = (*line, statement);
+            match command {
+                Command::While(_) => open.push(("WHILE", position)),
+                Command::Repeat => open.push(("REPEAT", position)),
+                Command::BlockIf(_) => open.push(("IF", position)),
+                Command::Wend => {
+                    let start = close(&mut open, "WHILE", "WEND", position)?;
+                    partners.insert(start, position);
+                    partners.insert(position, start);
+                }
+                Command::Until(_) => {
+                    let start = close(&mut open, "REPEAT", "UNTIL", position)?;
+                    partners.insert(position, start);
+                }
+                Command::Else => {
+                    let start = close(&mut open, "IF", "ELSE", position)?;
+                    partners.insert(start, position);
+                    open.push(("ELSE", position));
+                }
+                Command::EndIf => {
+                    let start = match open.last() {
+                        Some(("ELSE", _)) => close(&mut open, "ELSE", "END IF", position)?,
+                        _ => close(&mut open, "IF", "END IF", position)?,
+                    };
+                    partners.insert(start, position);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    match open.pop() {
+        Some((found, (line, statement))) => Err(BlockError {
+            found,
+            missing: match found {
+                "WHILE" => "WEND",
+                "REPEAT" => "UNTIL",
+                _ => "END IF",
+            },
+            line,
+            statement: statement + 1,
+        }),
+        None => Ok(partners),
+    }
+}
+
+// Ends the innermost open block, which has to have been started by `start`
+fn close(
+    open: &mut Vec<(&'static str, Position)>,
+    start: &'static str,
+    end: &'static str,
+    (line, statement): Position,
+) -> Result<Position, BlockError> {
+    match open.pop() {
+        Some((keyword, position)) if keyword == start => Ok(position),
+        _ => Err(BlockError {
+            found: end,
+            missing: start,
+            line,
+            statement: statement + 1,
+        }),
+    }
+}
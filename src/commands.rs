@@ -1,35 +1,269 @@
 use std::fmt::Display;
 
-pub type Line = (usize, Command);
+use crate::parsers::{
+    expressions::{join, ExpressionTarget, Slice, SliceRange},
+    generic::quote_string,
+};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+pub type Line = (usize, Vec<Command>);
+
+// Control variable, start, limit and optional step
+pub type ForLoop = (
+    String,
+    ExpressionTarget,
+    ExpressionTarget,
+    Option<ExpressionTarget>,
+);
+
+// Function name, parameter names and the expression it evaluates
+pub type FunctionDefinition = (String, Vec<String>, ExpressionTarget);
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Command {
-    Print(PrintOutput),
-    GoTo(usize),
-    Var((String, Primitive)),
-    Comment,
+    Print(Vec<PrintOutput>),
+    GoTo(ExpressionTarget),
+    GoSub(ExpressionTarget),
+    // ON x GO TO/GO SUB, holding the jump for each value of x from 1 upwards
+    On(ExpressionTarget, Vec<Command>),
+    Return,
+    Input(Vec<InputItem>),
+    Var((String, ExpressionTarget)),
+    Dim((String, Vec<ExpressionTarget>)),
+    SetElement((String, Vec<ExpressionTarget>, ExpressionTarget)),
+    SetSlice((String, Slice, ExpressionTarget)),
+    Data(Vec<ExpressionTarget>),
+    Read(Vec<ExpressionTarget>),
+    Restore(Option<usize>),
+    Randomize(Option<ExpressionTarget>),
+    If(ExpressionTarget, Box<Command>),
+    For(ForLoop),
+    Next(String),
+    Stop,
+    Continue,
+    DefFn(FunctionDefinition),
+    While(ExpressionTarget),
+    Wend,
+    Repeat,
+    Until(ExpressionTarget),
+    BlockIf(ExpressionTarget),
+    Else,
+    EndIf,
+    Comment(String),
     None,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// Lists a statement the way it would be typed back in
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Print(items) if items.is_empty() => write!(f, "PRINT"),
+            Command::Print(items) => {
+                write!(f, "PRINT ")?;
+                items.iter().try_for_each(|item| write!(f, "{}", item))
+            }
+            Command::GoTo(target) => write!(f, "GO TO {}", target),
+            Command::GoSub(target) => write!(f, "GO SUB {}", target),
+            Command::On(selector, jumps) => {
+                let keyword = match jumps.first() {
+                    Some(Command::GoSub(_)) => "GO SUB",
+                    _ => "GO TO",
+                };
+                let targets: Vec<ExpressionTarget> = jumps
+                    .iter()
+                    .filter_map(|jump| match jump {
+                        Command::GoTo(target) | Command::GoSub(target) => Some(target.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                write!(f, "ON {} {} {}", selector, keyword, join(&targets))
+            }
+            Command::Return => write!(f, "RETURN"),
+            Command::Input(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "INPUT {}", items.join(";"))
+            }
+            Command::Var((name, value)) => write!(f, "LET {}={}", name, value),
+            Command::Dim((name, dimensions)) => write!(f, "DIM {}({})", name, join(dimensions)),
+            Command::SetElement((name, subscripts, value)) => {
+                write!(f, "LET {}({})={}", name, join(subscripts), value)
+            }
+            Command::SetSlice((name, slice, value)) => {
+                write!(f, "LET {}{}={}", name, SliceRange(slice), value)
+            }
+            Command::Data(items) => write!(f, "DATA {}", join(items)),
+            Command::Read(targets) => write!(f, "READ {}", join(targets)),
+            Command::Restore(None) => write!(f, "RESTORE"),
+            Command::Restore(Some(line)) => write!(f, "RESTORE {}", line),
+            Command::Randomize(None) => write!(f, "RANDOMIZE"),
+            Command::Randomize(Some(seed)) => write!(f, "RANDOMIZE {}", seed),
+            Command::If(condition, command) => write!(f, "IF {} THEN {}", condition, command),
+            Command::For((variable, start, limit, step)) => {
+                write!(f, "FOR {}={} TO {}", variable, start, limit)?;
+                match step {
+                    Some(step) => write!(f, " STEP {}", step),
+                    None => Ok(()),
+                }
+            }
+            Command::Next(variable) => write!(f, "NEXT {}", variable),
+            Command::Stop => write!(f, "STOP"),
+            Command::Continue => write!(f, "CONTINUE"),
+            Command::DefFn((name, parameters, body)) => {
+                write!(f, "DEF FN {}({})={}", name, parameters.join(","), body)
+            }
+            Command::While(condition) => write!(f, "WHILE {}", condition),
+            Command::Wend => write!(f, "WEND"),
+            Command::Repeat => write!(f, "REPEAT"),
+            Command::Until(condition) => write!(f, "UNTIL {}", condition),
+            Command::BlockIf(condition) => write!(f, "IF {} THEN", condition),
+            Command::Else => write!(f, "ELSE"),
+            Command::EndIf => write!(f, "END IF"),
+            Command::Comment(text) if text.is_empty() => write!(f, "REM"),
+            Command::Comment(text) => write!(f, "REM {}", text),
+            Command::None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Primitive {
     Int(i64),
+    Float(f64),
     String(String),
-    Assignment(String),
 }
 
 impl Display for Primitive {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Primitive::Int(i) => write!(f, "{}", i),
+            // Whole numbers follow the same rules, so 100000*100000 prints as 1E+10
+            Primitive::Int(i) => write!(f, "{}", format_float(*i as f64)),
+            Primitive::Float(n) => write!(f, "{}", format_float(*n)),
             Primitive::String(s) => write!(f, "{}", s),
-            Primitive::Assignment(a) => write!(f, "Assigment from {}", a),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// Prints a number the way the Spectrum does: rounded to 8 significant
+// digits, and in E notation when it is very large or very small
+fn format_float(value: f64) -> String {
+    if value == 0.0 {
+        return String::from("0");
+    }
+
+    let sign = if value < 0.0 { "-" } else { "" };
+    let scientific = format!("{:.7e}", value.abs());
+    let Some((mantissa, exponent)) = scientific.split_once('e') else {
+        return scientific;
+    };
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let digits = mantissa.replace('.', "");
+    let digits = digits.trim_end_matches('0');
+
+    if !(-4..=8).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exponent_sign = if exponent < 0 { "-" } else { "+" };
+        return format!(
+            "{}{}{}{}E{}{}",
+            sign,
+            first,
+            point,
+            rest,
+            exponent_sign,
+            exponent.abs()
+        );
+    }
+
+    let formatted = if exponent < 0 {
+        format!("0.{}{}", "0".repeat((-exponent - 1) as usize), digits)
+    } else {
+        let whole = exponent as usize + 1;
+        if digits.len() > whole {
+            format!("{}.{}", &digits[..whole], &digits[whole..])
+        } else {
+            format!("{}{}", digits, "0".repeat(whole - digits.len()))
+        }
+    };
+
+    format!("{}{}", sign, formatted)
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum PrintOutput {
     Value(String),
+    Expression(ExpressionTarget),
+    Tab(ExpressionTarget),
+    At(ExpressionTarget, ExpressionTarget),
+    // ";" leaves the print position where it is
+    Adjacent,
+    // "," moves to the next 16 column zone
+    NextZone,
+    // "'" starts a new line
+    NewLine,
+}
+
+impl Display for PrintOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrintOutput::Value(value) => write!(f, "{}", quote_string(value)),
+            PrintOutput::Expression(expression) => write!(f, "{}", expression),
+            PrintOutput::Tab(column) => write!(f, "TAB {}", column),
+            PrintOutput::At(row, column) => write!(f, "AT {},{}", row, column),
+            PrintOutput::Adjacent => write!(f, ";"),
+            PrintOutput::NextZone => write!(f, ","),
+            PrintOutput::NewLine => write!(f, "'"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InputItem {
+    Prompt(String),
     Variable(String),
 }
+
+impl Display for InputItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputItem::Prompt(prompt) => write!(f, "{}", quote_string(prompt)),
+            InputItem::Variable(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Primitive;
+
+    #[test]
+    fn it_prints_numbers_like_a_spectrum() {
+        let cases = [
+            (1.0 / 3.0, "0.33333333"),
+            (2.0 / 3.0, "0.66666667"),
+            (1e10, "1E+10"),
+            (123456789.0, "123456790"),
+            (1.5, "1.5"),
+            (-0.25, "-0.25"),
+            (0.0001, "0.0001"),
+            (0.00002, "2E-5"),
+            (3.0, "3"),
+            (1.2345678e-7, "1.2345678E-7"),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(Primitive::Float(value).to_string(), expected);
+        }
+
+        let cases = [
+            (0, "0"),
+            (-42, "-42"),
+            (99999999, "99999999"),
+            (123456789, "123456790"),
+            (1234567890, "1.2345679E+9"),
+            (10000000000, "1E+10"),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(Primitive::Int(value).to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::{fmt::Display, io};
+
+// What went wrong, as one of the Spectrum's reports
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ErrorKind {
+    NextWithoutFor,
+    ForWithoutNext,
+    VariableNotFound,
+    SubscriptWrong,
+    OutOfData,
+    OutOfMemory,
+    NumberTooBig,
+    ReturnWithoutGoSub,
+    NonsenseInBasic,
+    InvalidArgument,
+    IntegerOutOfRange,
+    OutOfScreen,
+    // Not really an error, but STOP is reported the same way as one
+    StopStatement,
+    FnWithoutDef,
+    ParameterError,
+    LineNotFound(usize),
+    StopInInput,
+    Io(io::ErrorKind),
+}
+
+impl ErrorKind {
+    // The character the Spectrum shows before the report's message. Missing lines
+    // and I/O failures have no report of their own, so borrow the closest ones.
+    pub fn code(&self) -> char {
+        match self {
+            ErrorKind::NextWithoutFor => '1',
+            ErrorKind::VariableNotFound => '2',
+            ErrorKind::SubscriptWrong => '3',
+            ErrorKind::OutOfMemory => '4',
+            ErrorKind::OutOfScreen => '5',
+            ErrorKind::NumberTooBig => '6',
+            ErrorKind::ReturnWithoutGoSub => '7',
+            ErrorKind::StopStatement => '9',
+            ErrorKind::InvalidArgument => 'A',
+            ErrorKind::IntegerOutOfRange => 'B',
+            ErrorKind::NonsenseInBasic => 'C',
+            ErrorKind::OutOfData => 'E',
+            ErrorKind::StopInInput => 'H',
+            ErrorKind::ForWithoutNext => 'I',
+            ErrorKind::Io(_) => 'J',
+            ErrorKind::LineNotFound(_) => 'N',
+            ErrorKind::FnWithoutDef => 'P',
+            ErrorKind::ParameterError => 'Q',
+        }
+    }
+}
+
+// A runtime error along with the line and statement it happened in, numbering
+// statements from 1 as the Spectrum does, e.g. "2 Variable not found, 60:1"
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub line: usize,
+    pub statement: usize,
+}
+
+// A block statement without its partner, e.g. "WEND without WHILE, 40:1" or
+// "IF without END IF, 10:1", found when the program is loaded
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BlockError {
+    pub found: &'static str,
+    pub missing: &'static str,
+    pub line: usize,
+    pub statement: usize,
+}
+
+// A statement from the structured dialect in a Sinclair program, e.g.
+// "WHILE needs the structured dialect, 10:1"
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DialectError {
+    pub keyword: &'static str,
+    pub line: usize,
+    pub statement: usize,
+}
+
+// A line of source that couldn't be parsed. Lines and columns count from 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl ParseError {
+    // `rest` is whatever was left of `text` when parsing stopped
+    pub fn new(line: usize, text: &str, rest: &str) -> Self {
+        let parsed = &text[..text.len() - rest.trim_start().len()];
+        ParseError {
+            line,
+            column: parsed.chars().count() + 1,
+            text: text.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LoadError {
+    Syntax(Vec<ParseError>),
+    Block(BlockError),
+    Dialect(DialectError),
+}
+
+impl From<io::Error> for ErrorKind {
+    fn from(value: io::Error) -> Self {
+        ErrorKind::Io(value.kind())
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::NextWithoutFor => write!(f, "NEXT without FOR"),
+            ErrorKind::ForWithoutNext => write!(f, "FOR without NEXT"),
+            ErrorKind::VariableNotFound => write!(f, "Variable not found"),
+            ErrorKind::SubscriptWrong => write!(f, "Subscript wrong"),
+            ErrorKind::OutOfData => write!(f, "Out of DATA"),
+            ErrorKind::OutOfMemory => write!(f, "Out of memory"),
+            ErrorKind::NumberTooBig => write!(f, "Number too big"),
+            ErrorKind::ReturnWithoutGoSub => write!(f, "RETURN without GO SUB"),
+            ErrorKind::NonsenseInBasic => write!(f, "Nonsense in BASIC"),
+            ErrorKind::InvalidArgument => write!(f, "Invalid argument"),
+            ErrorKind::IntegerOutOfRange => write!(f, "Integer out of range"),
+            ErrorKind::OutOfScreen => write!(f, "Out of screen"),
+            ErrorKind::StopStatement => write!(f, "STOP statement"),
+            ErrorKind::FnWithoutDef => write!(f, "FN without DEF"),
+            ErrorKind::ParameterError => write!(f, "Parameter error"),
+            ErrorKind::LineNotFound(line) => write!(f, "Line {} does not exist", line),
+            ErrorKind::StopInInput => write!(f, "STOP in INPUT"),
+            ErrorKind::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}, {}:{}",
+            self.kind.code(),
+            self.kind,
+            self.line,
+            self.statement
+        )
+    }
+}
+
+impl Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} without {}, {}:{}",
+            self.found, self.missing, self.line, self.statement
+        )
+    }
+}
+
+impl Display for DialectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} needs the structured dialect, {}:{}",
+            self.keyword, self.line, self.statement
+        )
+    }
+}
+
+// Shows the line with a caret under where it stopped making sense
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Syntax error at line {}, column {}:",
+            self.line, self.column
+        )?;
+        writeln!(f, "  {}", self.text)?;
+        write!(f, "  {}^", " ".repeat(self.column - 1))
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Syntax(errors) => {
+                let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            LoadError::Block(error) => write!(f, "{}", error),
+            LoadError::Dialect(error) => write!(f, "{}", error),
+        }
+    }
+}
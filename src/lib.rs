@@ -0,0 +1,7 @@
+pub mod arrays;
+pub mod basic;
+pub mod blocks;
+pub mod commands;
+pub mod errors;
+pub mod lines;
+pub mod parsers;
@@ -0,0 +1,76 @@
+use std::slice::Iter;
+
+use crate::commands::Line;
+
+// A program's lines, kept in order of line number so a line can be found with
+// a binary search and the next one is just the next index
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Lines {
+    lines: Vec<Line>,
+}
+
+impl Lines {
+    // Adds a line, replacing any line that already has its number
+    pub fn insert(&mut self, line: Line) {
+        match self.search(line.0) {
+            Ok(index) => self.lines[index] = line,
+            Err(index) => self.lines.insert(index, line),
+        }
+    }
+
+    pub fn remove(&mut self, line: usize) {
+        if let Ok(index) = self.search(line) {
+            self.lines.remove(index);
+        }
+    }
+
+    // The index of the line with this number
+    pub fn find_line(&self, line: usize) -> Option<usize> {
+        self.search(line).ok()
+    }
+
+    // The index of the first line numbered at or after `line`
+    pub fn find_next_line(&self, line: usize) -> usize {
+        self.lines.partition_point(|(number, _)| *number < line)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        self.lines.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, Line> {
+        self.lines.iter()
+    }
+
+    fn search(&self, line: usize) -> Result<usize, usize> {
+        self.lines
+            .binary_search_by_key(&line, |(number, _)| *number)
+    }
+}
+
+// Lines can come in any order. When two share a number the later one wins, as
+// if they had been typed in one after the other.
+impl FromIterator<Line> for Lines {
+    fn from_iter<T: IntoIterator<Item = Line>>(iter: T) -> Self {
+        let mut sorted: Vec<Line> = iter.into_iter().collect();
+        // The sort is stable, and takes linear time when the lines are already in order
+        sorted.sort_by_key(|(number, _)| *number);
+
+        let mut lines: Vec<Line> = Vec::with_capacity(sorted.len());
+        for line in sorted {
+            match lines.last_mut() {
+                Some(last) if last.0 == line.0 => *last = line,
+                _ => lines.push(line),
+            }
+        }
+        Lines { lines }
+    }
+}
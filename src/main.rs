@@ -1,12 +1,136 @@
-use std::fs;
+use std::{
+    env, fs,
+    io::{self, IsTerminal, Read},
+    process::ExitCode,
+};
 
-mod basic;
-mod commands;
-mod node;
-mod parsers;
+use basic_interpreter::basic::{Dialect, Program, ReportKind};
 
-fn main() {
-    let file = fs::read_to_string("./inputs/printing_program.bas").unwrap();
-    let mut program = basic::Program::from(file.as_str());
-    program.execute();
+mod repl;
+
+// Without a program to run, it starts an interactive session instead
+const USAGE: &str = "Usage: basic-interpreter [--check | --list] [--structured] [file | -]";
+
+// Exit codes, so the interpreter can be used from scripts
+const EXIT_RUNTIME_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_SYNTAX_ERROR: u8 = 3;
+const EXIT_STOP: u8 = 4;
+
+#[derive(PartialEq)]
+enum Mode {
+    Run,
+    Check,
+    List,
+}
+
+struct Options {
+    mode: Mode,
+    dialect: Dialect,
+    path: Option<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut mode = Mode::Run;
+    let mut dialect = Dialect::Sinclair;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--check" if mode == Mode::Run => mode = Mode::Check,
+            "--list" if mode == Mode::Run => mode = Mode::List,
+            "--check" | "--list" => return Err(String::from("Only one of --check and --list")),
+            "--structured" => dialect = Dialect::Structured,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ if path.is_some() => return Err(String::from("Only one program can be run")),
+            _ => path = Some(arg),
+        }
+    }
+
+    if path.is_none() && mode != Mode::Run {
+        return Err(String::from("No program given"));
+    }
+    Ok(Options {
+        mode,
+        dialect,
+        path,
+    })
+}
+
+// "-" reads the program from standard input
+fn read_source(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let Some(path) = options.path else {
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        return match repl::run(
+            options.dialect,
+            &mut stdin.lock(),
+            &mut io::stdout(),
+            prompt,
+        ) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::from(EXIT_RUNTIME_ERROR)
+            }
+        };
+    };
+
+    let source = match read_source(&path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Couldn't read {}: {}", path, error);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let mut program = match Program::load(&source, options.dialect) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::from(EXIT_SYNTAX_ERROR);
+        }
+    };
+
+    match options.mode {
+        Mode::Check => ExitCode::SUCCESS,
+        Mode::List => match program.list(&mut io::stdout()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::from(EXIT_RUNTIME_ERROR)
+            }
+        },
+        Mode::Run => match program.execute() {
+            Ok(report) => {
+                eprintln!("{}", report);
+                match report.kind {
+                    ReportKind::Ok => ExitCode::SUCCESS,
+                    ReportKind::Stop => ExitCode::from(EXIT_STOP),
+                }
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::from(EXIT_RUNTIME_ERROR)
+            }
+        },
+    }
 }
@@ -1,40 +1,262 @@
 use nom::{
-    branch::alt, bytes::complete::tag, character::complete::u64 as ccu64, combinator::map,
-    sequence::terminated, IResult,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, one_of, satisfy, space0, u64 as ccu64},
+    combinator::{eof, map, not, opt, peek, value, verify},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
 };
 
-use crate::commands::{Command, Line, PrintOutput};
+use crate::{
+    commands::{Command, ForLoop, FunctionDefinition, InputItem, Line, Primitive, PrintOutput},
+    errors::ParseError,
+};
 
-use super::{generic, variables};
+use super::{
+    expressions::{self, ExpressionTarget},
+    generic, variables,
+};
 
 pub fn match_command(i: &str) -> IResult<&str, &str> {
-    alt((tag("PRINT"), tag("GO TO"), tag("LET"), tag("REM")))(i)
+    alt((match_sinclair_command, match_structured_command))(i)
+}
+
+fn match_sinclair_command(i: &str) -> IResult<&str, &str> {
+    alt((
+        tag("PRINT"),
+        tag("GO TO"),
+        tag("GO SUB"),
+        tag("RETURN"),
+        tag("INPUT"),
+        tag("LET"),
+        tag("DIM"),
+        tag("DATA"),
+        tag("READ"),
+        tag("RESTORE"),
+        tag("RANDOMIZE"),
+        tag("REM"),
+        tag("IF"),
+        tag("FOR"),
+        tag("NEXT"),
+        tag("STOP"),
+        tag("CONTINUE"),
+        tag("DEF FN"),
+        tag("ON"),
+    ))(i)
+}
+
+// Only run in the structured dialect
+fn match_structured_command(i: &str) -> IResult<&str, &str> {
+    alt((
+        tag("WHILE"),
+        tag("WEND"),
+        tag("REPEAT"),
+        tag("UNTIL"),
+        tag("ELSE"),
+        tag("END IF"),
+    ))(i)
 }
 
-pub fn parse_print_command(i: &str) -> IResult<&str, PrintOutput> {
+fn parse_print_item(i: &str) -> IResult<&str, PrintOutput> {
+    let keyword = |name| terminated(tag(name), pair(not(satisfy(char::is_alphanumeric)), space0));
     alt((
         map(
-            alt((
-                variables::parse_str_variable_name,
-                variables::parse_int_variable_name,
-            )),
-            PrintOutput::Variable,
+            preceded(keyword("TAB"), expressions::parse_expression),
+            PrintOutput::Tab,
         ),
-        map(generic::read_string, PrintOutput::Value),
+        map(
+            preceded(
+                keyword("AT"),
+                separated_pair(
+                    expressions::parse_expression,
+                    delimited(space0, char(','), space0),
+                    expressions::parse_expression,
+                ),
+            ),
+            |(row, column)| PrintOutput::At(row, column),
+        ),
+        map(
+            expressions::parse_expression,
+            |expression| match expression {
+                ExpressionTarget::Val(Primitive::String(value)) => PrintOutput::Value(value),
+                expression => PrintOutput::Expression(expression),
+            },
+        ),
+    ))(i)
+}
+
+fn parse_print_separator(i: &str) -> IResult<&str, PrintOutput> {
+    alt((
+        value(PrintOutput::Adjacent, char(';')),
+        value(PrintOutput::NextZone, char(',')),
+        value(PrintOutput::NewLine, char('\'')),
     ))(i)
 }
 
+// Items are separated by ";", "," or "'", and any of them may be left out
+pub fn parse_print_command(i: &str) -> IResult<&str, Vec<PrintOutput>> {
+    let (i, (first, rest)) = pair(
+        opt(parse_print_item),
+        many0(pair(
+            delimited(space0, parse_print_separator, space0),
+            opt(parse_print_item),
+        )),
+    )(i)?;
+
+    let mut items: Vec<PrintOutput> = first.into_iter().collect();
+    for (separator, item) in rest {
+        items.push(separator);
+        items.extend(item);
+    }
+    Ok((i, items))
+}
+
+// "IF ... THEN" with nothing after it starts a block that runs up to ELSE or END IF
+pub fn parse_block_if_command(i: &str) -> IResult<&str, ExpressionTarget> {
+    terminated(
+        expressions::parse_expression,
+        tuple((
+            delimited(space0, tag("THEN"), space0),
+            peek(alt((eof, tag("\n")))),
+        )),
+    )(i)
+}
+
+// The statement after THEN may be a bare line number, which is shorthand for GO TO
+pub fn parse_if_command(i: &str) -> IResult<&str, (ExpressionTarget, Command)> {
+    let (i, condition) = expressions::parse_expression(i)?;
+    let (i, _) = delimited(space0, tag("THEN"), space0)(i)?;
+    let (i, command) = alt((
+        map(ccu64, |line| {
+            Command::GoTo(ExpressionTarget::from(line as i64))
+        }),
+        parse_command,
+    ))(i)?;
+    Ok((i, (condition, command)))
+}
+
+pub fn parse_for_command(i: &str) -> IResult<&str, ForLoop> {
+    let (i, variable) = variables::parse_control_variable_name(i)?;
+    let (i, _) = tag("=")(i)?;
+    let (i, start) = expressions::parse_expression(i)?;
+    let (i, _) = delimited(space0, tag("TO"), space0)(i)?;
+    let (i, limit) = expressions::parse_expression(i)?;
+    let (i, step) = opt(preceded(
+        delimited(space0, tag("STEP"), space0),
+        expressions::parse_expression,
+    ))(i)?;
+    Ok((i, (variable, start, limit, step)))
+}
+
+// Functions and their parameters have single letter names, e.g. "s$(a$,n)"
+pub fn parse_def_fn_command(i: &str) -> IResult<&str, FunctionDefinition> {
+    let (i, name) = variables::parse_array_name(i)?;
+    let (i, parameters) = delimited(
+        pair(char('('), space0),
+        separated_list0(
+            delimited(space0, char(','), space0),
+            variables::parse_array_name,
+        ),
+        pair(space0, char(')')),
+    )(i)?;
+    let (i, _) = delimited(space0, tag("="), space0)(i)?;
+    let (i, body) = expressions::parse_expression(i)?;
+    Ok((i, (name, parameters, body)))
+}
+
+// "ON x GO TO 100,200,300" or "ON x GO SUB ..."
+pub fn parse_on_command(i: &str) -> IResult<&str, (ExpressionTarget, Vec<Command>)> {
+    let (i, selector) = expressions::parse_expression(i)?;
+    let (i, jump) = delimited(space0, alt((tag("GO TO"), tag("GO SUB"))), space0)(i)?;
+    let (i, targets) = separated_list1(
+        delimited(space0, char(','), space0),
+        expressions::parse_expression,
+    )(i)?;
+
+    let jumps = targets
+        .into_iter()
+        .map(|target| match jump {
+            "GO TO" => Command::GoTo(target),
+            _ => Command::GoSub(target),
+        })
+        .collect();
+    Ok((i, (selector, jumps)))
+}
+
+pub fn parse_input_command(i: &str) -> IResult<&str, Vec<InputItem>> {
+    separated_list1(
+        delimited(space0, one_of(";,"), space0),
+        alt((
+            map(generic::read_string, InputItem::Prompt),
+            map(
+                alt((
+                    variables::parse_str_variable_name,
+                    variables::parse_int_variable_name,
+                )),
+                InputItem::Variable,
+            ),
+        )),
+    )(i)
+}
+
 pub fn parse_command(i: &str) -> IResult<&str, Command> {
     let (i, command): (&str, &str) = match_command(i).unwrap_or((i, ""));
-    let (i, _) = tag(" ")(i)?;
+    let (i, _) = space0(i)?;
 
     let (i, cmd) = match command {
         "PRINT" => map(parse_print_command, Command::Print)(i)?,
-        "GO TO" => map(ccu64, |line| Command::GoTo(line as usize))(i)?,
-        "LET" => map(variables::parse_var, Command::Var)(i)?,
+        "GO TO" => map(expressions::parse_expression, Command::GoTo)(i)?,
+        "GO SUB" => map(expressions::parse_expression, Command::GoSub)(i)?,
+        "ON" => map(parse_on_command, |(selector, jumps)| {
+            Command::On(selector, jumps)
+        })(i)?,
+        "RETURN" => (i, Command::Return),
+        "INPUT" => map(parse_input_command, Command::Input)(i)?,
+        "LET" => alt((
+            map(variables::parse_slice_assignment, Command::SetSlice),
+            map(variables::parse_element_assignment, Command::SetElement),
+            map(variables::parse_var, Command::Var),
+        ))(i)?,
+        "DIM" => map(variables::parse_dim, Command::Dim)(i)?,
+        "DATA" => map(
+            separated_list1(
+                delimited(space0, char(','), space0),
+                expressions::parse_expression,
+            ),
+            Command::Data,
+        )(i)?,
+        "READ" => map(
+            separated_list1(
+                delimited(space0, char(','), space0),
+                variables::parse_target,
+            ),
+            Command::Read,
+        )(i)?,
+        "RANDOMIZE" => map(opt(expressions::parse_expression), Command::Randomize)(i)?,
+        "RESTORE" => map(opt(ccu64), |line| {
+            Command::Restore(line.map(|l| l as usize))
+        })(i)?,
+        "IF" => alt((
+            map(parse_block_if_command, Command::BlockIf),
+            map(parse_if_command, |(condition, command)| {
+                Command::If(condition, Box::new(command))
+            }),
+        ))(i)?,
+        "WHILE" => map(expressions::parse_expression, Command::While)(i)?,
+        "WEND" => (i, Command::Wend),
+        "REPEAT" => (i, Command::Repeat),
+        "UNTIL" => map(expressions::parse_expression, Command::Until)(i)?,
+        "ELSE" => (i, Command::Else),
+        "END IF" => (i, Command::EndIf),
+        "FOR" => map(parse_for_command, Command::For)(i)?,
+        "NEXT" => map(variables::parse_control_variable_name, Command::Next)(i)?,
+        "STOP" => (i, Command::Stop),
+        "CONTINUE" => (i, Command::Continue),
+        "DEF FN" => map(parse_def_fn_command, Command::DefFn)(i)?,
         "REM" => {
-            let (i, _) = generic::consume_line(i)?;
-            (i, Command::Comment)
+            let (i, text) = generic::consume_line(i)?;
+            (i, Command::Comment(text.trim_end().to_string()))
         }
         _ => (i, Command::None),
     };
@@ -42,8 +264,34 @@ pub fn parse_command(i: &str) -> IResult<&str, Command> {
     Ok((i, cmd))
 }
 
+// One or more statements separated by colons
+pub fn parse_statements(i: &str) -> IResult<&str, Vec<Command>> {
+    separated_list1(delimited(space0, char(':'), space0), parse_command)(i)
+}
+
+// Lines are numbered from 1, leaving 0 for statements run in immediate mode
 pub fn parse_line(line: &str) -> IResult<&str, Line> {
-    let (i, line_number) = map(terminated(ccu64, tag(" ")), |l| l as usize)(line)?;
-    let (i, command) = parse_command(i)?;
-    Ok((i, (line_number, command)))
+    let (i, line_number) = map(
+        terminated(verify(ccu64, |line| *line >= 1), tag(" ")),
+        |l| l as usize,
+    )(line)?;
+    let (i, commands) = parse_statements(i)?;
+    Ok((i, (line_number, commands)))
+}
+
+// Runs `parser` over the whole of `text`, which is line `line` of the source,
+// and reports where it stopped if anything is left over
+pub fn parse_all<'a, T>(
+    line: usize,
+    text: &'a str,
+    mut parser: impl FnMut(&'a str) -> IResult<&'a str, T>,
+) -> Result<T, ParseError> {
+    match parser(text) {
+        Ok((rest, parsed)) if rest.trim().is_empty() => Ok(parsed),
+        Ok((rest, _)) => Err(ParseError::new(line, text, rest)),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => {
+            Err(ParseError::new(line, text, error.input))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(ParseError::new(line, text, "")),
+    }
 }
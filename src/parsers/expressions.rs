@@ -1,13 +1,21 @@
+use std::fmt::Display;
+
 use nom::{
-    character::{complete::i64 as cci64, streaming::one_of},
-    combinator::{map, value},
-    sequence::tuple,
-    IResult, branch::alt, multi::many0,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit0, digit1, one_of, satisfy, space0},
+    combinator::{map, map_res, not, opt, recognize, value},
+    multi::{separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
 };
 
 use crate::commands::Primitive;
 
-use super::variables::parse_int;
+use super::{
+    generic::{quote_string, read_string},
+    variables::{parse_array_name, parse_int_variable_name, parse_str_variable_name},
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Operator {
@@ -15,26 +23,260 @@ pub enum Operator {
     Subtract,
     Divide,
     Multiply,
+    Power,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    And,
+    Or,
 }
 
-impl From<char> for Operator {
-    fn from(value: char) -> Self {
-        match value {
-            '+' => Operator::Add,
-            '-' => Operator::Subtract,
-            '/' => Operator::Divide,
-            '*' => Operator::Multiply,
-            _ => panic!("Unrecognised character"),
+impl Operator {
+    // Sinclair BASIC operator priorities, higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Or => 2,
+            Operator::And => 3,
+            Operator::Equal
+            | Operator::NotEqual
+            | Operator::LessThan
+            | Operator::GreaterThan
+            | Operator::LessThanOrEqual
+            | Operator::GreaterThanOrEqual => 5,
+            Operator::Add | Operator::Subtract => 6,
+            Operator::Multiply | Operator::Divide => 8,
+            Operator::Power => 10,
         }
     }
 }
 
-pub type Expression = (ExpressionTarget, Operator, ExpressionTarget);
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum UnaryOperator {
+    Minus,
+    Not,
+}
+
+impl UnaryOperator {
+    pub fn precedence(&self) -> u8 {
+        match self {
+            UnaryOperator::Not => 4,
+            UnaryOperator::Minus => 9,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sqr,
+    Int,
+    Abs,
+    Sgn,
+    Exp,
+    Ln,
+    Pi,
+    Rnd,
+    Len,
+    Chr,
+    Code,
+    Str,
+    Val,
+    ValStr,
+}
+
+impl Function {
+    // Functions bind more tightly than any operator, so "SQR 9+7" is (SQR 9)+7
+    pub fn precedence(&self) -> u8 {
+        11
+    }
+
+    pub fn takes_argument(&self) -> bool {
+        !matches!(self, Function::Pi | Function::Rnd)
+    }
+}
+
+pub type Expression = (ExpressionTarget, Operator, ExpressionTarget);
+
+// The start and end of a string slice, either of which may be left out
+pub type Slice = (Option<ExpressionTarget>, Option<ExpressionTarget>);
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum ExpressionTarget {
     Val(Primitive),
+    Variable(String),
     Expression(Box<Expression>),
+    Unary(UnaryOperator, Box<ExpressionTarget>),
+    Element(String, Vec<ExpressionTarget>),
+    Function(Function, Option<Box<ExpressionTarget>>),
+    Slice(Box<ExpressionTarget>, Box<Slice>),
+    // A call to a function defined with DEF FN
+    Call(String, Vec<ExpressionTarget>),
+}
+
+impl ExpressionTarget {
+    // How tightly the expression holds together when listed, so we know when
+    // it needs brackets. Values and variables never do.
+    fn precedence(&self) -> u8 {
+        match self {
+            ExpressionTarget::Expression(expression) => expression.1.precedence(),
+            ExpressionTarget::Unary(operator, _) => operator.precedence(),
+            ExpressionTarget::Function(function, _) => function.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+// Wraps an operand in brackets when it binds less tightly than `precedence`
+struct Operand<'a>(&'a ExpressionTarget, u8);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Operand(target, precedence) = self;
+        if target.precedence() < *precedence {
+            write!(f, "({})", target)
+        } else {
+            write!(f, "{}", target)
+        }
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Divide => "/",
+            Operator::Multiply => "*",
+            Operator::Power => "^",
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::LessThan => "<",
+            Operator::GreaterThan => ">",
+            Operator::LessThanOrEqual => "<=",
+            Operator::GreaterThanOrEqual => ">=",
+            Operator::And => " AND ",
+            Operator::Or => " OR ",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Function::Sin => "SIN",
+            Function::Cos => "COS",
+            Function::Tan => "TAN",
+            Function::Sqr => "SQR",
+            Function::Int => "INT",
+            Function::Abs => "ABS",
+            Function::Sgn => "SGN",
+            Function::Exp => "EXP",
+            Function::Ln => "LN",
+            Function::Pi => "PI",
+            Function::Rnd => "RND",
+            Function::Len => "LEN",
+            Function::Chr => "CHR$",
+            Function::Code => "CODE",
+            Function::Str => "STR$",
+            Function::Val => "VAL",
+            Function::ValStr => "VAL$",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Lists an expression as it would be typed, adding only the brackets needed
+// for it to parse back the same way
+impl Display for ExpressionTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpressionTarget::Val(Primitive::String(value)) => {
+                write!(f, "{}", quote_string(value))
+            }
+            // Literals are written in full rather than rounded the way PRINT does,
+            // so the number that's read back is the same one
+            ExpressionTarget::Val(Primitive::Int(value)) => write!(f, "{}", value),
+            ExpressionTarget::Val(Primitive::Float(value)) => {
+                write!(f, "{}", format!("{:?}", value).replace('e', "E"))
+            }
+            ExpressionTarget::Variable(name) => write!(f, "{}", name),
+            ExpressionTarget::Expression(expression) => {
+                let (lhs, operator, rhs) = expression.as_ref();
+                let precedence = operator.precedence();
+                write!(
+                    f,
+                    "{}{}{}",
+                    Operand(lhs, precedence),
+                    operator,
+                    Operand(rhs, precedence + 1)
+                )
+            }
+            ExpressionTarget::Unary(UnaryOperator::Minus, operand) => {
+                write!(
+                    f,
+                    "-{}",
+                    Operand(operand, UnaryOperator::Minus.precedence() + 1)
+                )
+            }
+            ExpressionTarget::Unary(UnaryOperator::Not, operand) => {
+                write!(
+                    f,
+                    "NOT {}",
+                    Operand(operand, UnaryOperator::Not.precedence() + 1)
+                )
+            }
+            ExpressionTarget::Element(name, subscripts) => {
+                write!(f, "{}({})", name, join(subscripts))
+            }
+            ExpressionTarget::Function(function, None) => write!(f, "{}", function),
+            ExpressionTarget::Function(function, Some(argument)) => {
+                write!(
+                    f,
+                    "{} {}",
+                    function,
+                    Operand(argument, function.precedence())
+                )
+            }
+            ExpressionTarget::Slice(target, slice) => {
+                write!(f, "{}{}", Operand(target, u8::MAX), SliceRange(slice))
+            }
+            ExpressionTarget::Call(name, arguments) => {
+                write!(f, "FN {}({})", name, join(arguments))
+            }
+        }
+    }
+}
+
+// The bracketed part of a slice, e.g. "(2 TO 5)" or "( TO 3)"
+pub struct SliceRange<'a>(pub &'a Slice);
+
+impl Display for SliceRange<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (start, end) = self.0;
+        match start {
+            Some(start) => write!(f, "({} TO ", start)?,
+            None => write!(f, "( TO ")?,
+        }
+        match end {
+            Some(end) => write!(f, "{})", end),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+// Expressions separated by commas, as in subscripts and argument lists
+pub fn join(expressions: &[ExpressionTarget]) -> String {
+    expressions
+        .iter()
+        .map(|expression| expression.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
 impl From<i64> for ExpressionTarget {
@@ -49,32 +291,215 @@ impl From<Expression> for ExpressionTarget {
     }
 }
 
-fn parse_expression_target(i: &str) -> IResult<&str, ExpressionTarget> {
+// Numbers may have a decimal point and an exponent, e.g. 1.5, .5 or 2E-3. Whole
+// numbers are kept as integers unless they are too big to fit in one.
+fn parse_number(i: &str) -> IResult<&str, Primitive> {
+    map_res(
+        recognize(pair(
+            alt((
+                recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+                recognize(pair(char('.'), digit1)),
+            )),
+            opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+        )),
+        |number: &str| match number.parse::<i64>() {
+            Ok(value) => Ok(Primitive::Int(value)),
+            Err(_) => match number.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(Primitive::Float(value)),
+                _ => Err(()),
+            },
+        },
+    )(i)
+}
+
+pub fn parse_subscripts(i: &str) -> IResult<&str, Vec<ExpressionTarget>> {
+    delimited(
+        pair(char('('), space0),
+        separated_list1(delimited(space0, char(','), space0), parse_expression),
+        pair(space0, char(')')),
+    )(i)
+}
+
+fn parse_function_name(i: &str) -> IResult<&str, Function> {
+    terminated(
+        alt((
+            value(Function::Sin, tag("SIN")),
+            value(Function::Cos, tag("COS")),
+            value(Function::Tan, tag("TAN")),
+            value(Function::Sqr, tag("SQR")),
+            value(Function::Int, tag("INT")),
+            value(Function::Abs, tag("ABS")),
+            value(Function::Sgn, tag("SGN")),
+            value(Function::Exp, tag("EXP")),
+            value(Function::Ln, tag("LN")),
+            value(Function::Pi, tag("PI")),
+            value(Function::Rnd, tag("RND")),
+            value(Function::Len, tag("LEN")),
+            value(Function::Chr, tag("CHR$")),
+            value(Function::Code, tag("CODE")),
+            value(Function::Str, tag("STR$")),
+            value(Function::ValStr, tag("VAL$")),
+            value(Function::Val, tag("VAL")),
+        )),
+        not(satisfy(|c| c.is_alphanumeric())),
+    )(i)
+}
+
+// The argument doesn't need brackets, so both "SQR 9" and "SQR (9)" work
+fn parse_function(i: &str) -> IResult<&str, ExpressionTarget> {
+    let (i, function) = parse_function_name(i)?;
+    if !function.takes_argument() {
+        return Ok((i, ExpressionTarget::Function(function, None)));
+    }
+
+    let (i, _) = space0(i)?;
+    let (i, argument) = parse_precedence(i, function.precedence() + 1)?;
+    Ok((
+        i,
+        ExpressionTarget::Function(function, Some(Box::new(argument))),
+    ))
+}
+
+// "a$(2 TO 5)", "a$( TO 3)" or "a$(4 TO )". A single character, "a$(3)", looks
+// just like an array element so is parsed as one.
+pub fn parse_slice(i: &str) -> IResult<&str, Slice> {
+    let to = terminated(tag("TO"), not(satisfy(|c| c.is_alphanumeric())));
+    delimited(
+        pair(char('('), space0),
+        separated_pair(
+            opt(preceded(not(to), parse_expression)),
+            delimited(space0, tag("TO"), space0),
+            opt(parse_expression),
+        ),
+        pair(space0, char(')')),
+    )(i)
+}
+
+fn parse_operand(i: &str) -> IResult<&str, ExpressionTarget> {
+    let (mut i, mut operand) = parse_primary(i)?;
+    while let Ok((rest, slice)) = parse_slice(i) {
+        operand = ExpressionTarget::Slice(Box::new(operand), Box::new(slice));
+        i = rest;
+    }
+    Ok((i, operand))
+}
+
+// "FN f(2,3)". The brackets are needed even when there are no arguments.
+fn parse_call(i: &str) -> IResult<&str, ExpressionTarget> {
+    let (i, _) = pair(tag("FN"), space0)(i)?;
+    let (i, name) = parse_array_name(i)?;
+    let (i, arguments) = delimited(
+        pair(char('('), space0),
+        separated_list0(delimited(space0, char(','), space0), parse_expression),
+        pair(space0, char(')')),
+    )(i)?;
+    Ok((i, ExpressionTarget::Call(name, arguments)))
+}
+
+fn parse_primary(i: &str) -> IResult<&str, ExpressionTarget> {
     alt((
-        map(parse_expression, ExpressionTarget::from),
-        map(cci64, ExpressionTarget::from)
+        delimited(
+            pair(char('('), space0),
+            parse_expression,
+            pair(space0, char(')')),
+        ),
+        map(parse_number, ExpressionTarget::Val),
+        map(read_string, |s| ExpressionTarget::Val(Primitive::String(s))),
+        parse_function,
+        parse_call,
+        map(
+            pair(parse_array_name, parse_subscripts),
+            |(id, subscripts)| ExpressionTarget::Element(id, subscripts),
+        ),
+        map(
+            alt((parse_str_variable_name, parse_int_variable_name)),
+            ExpressionTarget::Variable,
+        ),
     ))(i)
 }
 
-pub fn parse_expression(i: &str) -> IResult<&str, Expression> {
-    tuple((map(cci64, ExpressionTarget::from),
-    map(one_of("*/+-"), Operator::from),
-    map(cci64, ExpressionTarget::from)))(i)
+fn parse_unary_operator(i: &str) -> IResult<&str, UnaryOperator> {
+    alt((
+        value(UnaryOperator::Minus, char('-')),
+        value(
+            UnaryOperator::Not,
+            terminated(
+                tag("NOT"),
+                pair(not(satisfy(|c| c.is_alphanumeric())), space0),
+            ),
+        ),
+    ))(i)
 }
 
-pub fn parse_full_expression(i: &str) -> IResult<&str, Expression> {
-    tuple((
-        parse_expression_target,
-        map(one_of("*/+-"), Operator::from),
-        parse_expression_target,
+// A unary operator applies to everything that binds more tightly than it does,
+// so "-2^2" is -(2^2) but "-2*3" is (-2)*3, and "NOT a=b" is NOT (a=b)
+fn parse_unary(i: &str) -> IResult<&str, ExpressionTarget> {
+    if let Ok((i, operator)) = parse_unary_operator(i) {
+        let (i, operand) = parse_precedence(i, operator.precedence() + 1)?;
+        return Ok((i, ExpressionTarget::Unary(operator, Box::new(operand))));
+    }
+
+    alt((
+        preceded(char('+'), |i| {
+            parse_precedence(i, UnaryOperator::Minus.precedence() + 1)
+        }),
+        parse_operand,
     ))(i)
 }
 
+fn parse_operator(i: &str) -> IResult<&str, Operator> {
+    delimited(space0, parse_operator_symbol, space0)(i)
+}
+
+fn parse_operator_symbol(i: &str) -> IResult<&str, Operator> {
+    alt((
+        value(Operator::Multiply, char('*')),
+        value(Operator::Divide, char('/')),
+        value(Operator::Add, char('+')),
+        value(Operator::Subtract, char('-')),
+        value(Operator::Power, char('^')),
+        value(Operator::NotEqual, tag("<>")),
+        value(Operator::LessThanOrEqual, tag("<=")),
+        value(Operator::GreaterThanOrEqual, tag(">=")),
+        value(Operator::Equal, tag("=")),
+        value(Operator::LessThan, tag("<")),
+        value(Operator::GreaterThan, tag(">")),
+        value(Operator::And, tag("AND")),
+        value(Operator::Or, tag("OR")),
+    ))(i)
+}
+
+// Precedence climbing: keep folding operators into the left-hand side for as
+// long as they bind at least as tightly as `min_precedence`. Operators of
+// equal priority are applied left-to-right, as on the Spectrum.
+fn parse_precedence(i: &str, min_precedence: u8) -> IResult<&str, ExpressionTarget> {
+    let (mut i, mut lhs) = parse_unary(i)?;
+
+    while let Ok((rest, operator)) = parse_operator(i) {
+        let precedence = operator.precedence();
+        if precedence < min_precedence {
+            break;
+        }
+
+        let (rest, rhs) = parse_precedence(rest, precedence + 1)?;
+        lhs = ExpressionTarget::from((lhs, operator, rhs));
+        i = rest;
+    }
+
+    Ok((i, lhs))
+}
+
+pub fn parse_expression(i: &str) -> IResult<&str, ExpressionTarget> {
+    parse_precedence(i, 0)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::commands::Primitive;
 
-    use super::{parse_full_expression, Expression, ExpressionTarget, Operator};
+    use super::{
+        parse_expression, Expression, ExpressionTarget, Function, Operator, UnaryOperator,
+    };
 
     #[test]
     fn it_parses_a_simple_expression() {
@@ -84,8 +509,8 @@ mod tests {
             Operator::Add,
             ExpressionTarget::Val(Primitive::Int(1)),
         );
-        let (_, result) = parse_full_expression(input).unwrap();
-        assert_eq!(expected, result);
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
     }
 
     #[test]
@@ -96,8 +521,8 @@ mod tests {
             Operator::Subtract,
             ExpressionTarget::Val(Primitive::Int(1)),
         );
-        let (_, result) = parse_full_expression(input).unwrap();
-        assert_eq!(expected, result);
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
     }
 
     #[test]
@@ -107,13 +532,288 @@ mod tests {
             ExpressionTarget::Expression(Box::new((
                 ExpressionTarget::Val(Primitive::Int(1)),
                 Operator::Add,
-                ExpressionTarget::Val(Primitive::Int(1))
+                ExpressionTarget::Val(Primitive::Int(1)),
             ))),
             Operator::Add,
-            ExpressionTarget::Val(Primitive::Int(2))
+            ExpressionTarget::Val(Primitive::Int(2)),
         );
 
-        let (_, result) = parse_full_expression(input).unwrap();
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_parses_variables_as_operands() {
+        let input = "price*qty";
+        let expected: Expression = (
+            ExpressionTarget::Variable(String::from("price")),
+            Operator::Multiply,
+            ExpressionTarget::Variable(String::from("qty")),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_parses_a_single_value_as_an_expression() {
+        let (_, result) = parse_expression("a$").unwrap();
+        assert_eq!(ExpressionTarget::Variable(String::from("a$")), result);
+
+        let (_, result) = parse_expression("42").unwrap();
+        assert_eq!(ExpressionTarget::from(42), result);
+    }
+
+    #[test]
+    fn it_binds_multiplication_tighter_than_addition() {
+        let input = "1+2*3";
+        let expected: Expression = (
+            ExpressionTarget::from(1),
+            Operator::Add,
+            ExpressionTarget::from((
+                ExpressionTarget::from(2),
+                Operator::Multiply,
+                ExpressionTarget::from(3),
+            )),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_parses_parentheses() {
+        let input = "(1+2)*3";
+        let expected: Expression = (
+            ExpressionTarget::from((
+                ExpressionTarget::from(1),
+                Operator::Add,
+                ExpressionTarget::from(2),
+            )),
+            Operator::Multiply,
+            ExpressionTarget::from(3),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_binds_exponentiation_tighter_than_unary_minus() {
+        let input = "-2^2";
+        let expected = ExpressionTarget::Unary(
+            UnaryOperator::Minus,
+            Box::new(ExpressionTarget::from((
+                ExpressionTarget::from(2),
+                Operator::Power,
+                ExpressionTarget::from(2),
+            ))),
+        );
+        let (_, result) = parse_expression(input).unwrap();
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn it_binds_unary_minus_tighter_than_multiplication() {
+        let input = "-2*+3";
+        let expected: Expression = (
+            ExpressionTarget::Unary(UnaryOperator::Minus, Box::new(ExpressionTarget::from(2))),
+            Operator::Multiply,
+            ExpressionTarget::from(3),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_binds_relational_operators_looser_than_arithmetic() {
+        let input = "a+1<=b";
+        let expected: Expression = (
+            ExpressionTarget::from((
+                ExpressionTarget::Variable(String::from("a")),
+                Operator::Add,
+                ExpressionTarget::from(1),
+            )),
+            Operator::LessThanOrEqual,
+            ExpressionTarget::Variable(String::from("b")),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_binds_and_tighter_than_or() {
+        let input = "a OR b>0 AND c$=\"Y\"";
+        let expected: Expression = (
+            ExpressionTarget::Variable(String::from("a")),
+            Operator::Or,
+            ExpressionTarget::from((
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("b")),
+                    Operator::GreaterThan,
+                    ExpressionTarget::from(0),
+                )),
+                Operator::And,
+                ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("c$")),
+                    Operator::Equal,
+                    ExpressionTarget::Val(Primitive::String(String::from("Y"))),
+                )),
+            )),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_binds_not_looser_than_comparisons() {
+        let input = "NOT a=b AND c";
+        let expected: Expression = (
+            ExpressionTarget::Unary(
+                UnaryOperator::Not,
+                Box::new(ExpressionTarget::from((
+                    ExpressionTarget::Variable(String::from("a")),
+                    Operator::Equal,
+                    ExpressionTarget::Variable(String::from("b")),
+                ))),
+            ),
+            Operator::And,
+            ExpressionTarget::Variable(String::from("c")),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_parses_an_array_element() {
+        let input = "m(i,j+1)*2";
+        let expected: Expression = (
+            ExpressionTarget::Element(
+                String::from("m"),
+                vec![
+                    ExpressionTarget::Variable(String::from("i")),
+                    ExpressionTarget::from((
+                        ExpressionTarget::Variable(String::from("j")),
+                        Operator::Add,
+                        ExpressionTarget::from(1),
+                    )),
+                ],
+            ),
+            Operator::Multiply,
+            ExpressionTarget::from(2),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_parses_decimal_and_exponent_literals() {
+        let cases = [
+            ("1.5", Primitive::Float(1.5)),
+            (".5", Primitive::Float(0.5)),
+            ("2E-3", Primitive::Float(0.002)),
+            ("1e10", Primitive::Float(1e10)),
+            ("42", Primitive::Int(42)),
+            ("99999999999999999999", Primitive::Float(1e20)),
+        ];
+
+        for (input, expected) in cases {
+            let (_, result) = parse_expression(input).unwrap();
+            assert_eq!(ExpressionTarget::Val(expected), result);
+        }
+    }
+
+    #[test]
+    fn it_binds_functions_tighter_than_operators() {
+        let input = "SQR 9+INT (x/2)*PI";
+        let expected: Expression = (
+            ExpressionTarget::Function(Function::Sqr, Some(Box::new(ExpressionTarget::from(9)))),
+            Operator::Add,
+            ExpressionTarget::from((
+                ExpressionTarget::Function(
+                    Function::Int,
+                    Some(Box::new(ExpressionTarget::from((
+                        ExpressionTarget::Variable(String::from("x")),
+                        Operator::Divide,
+                        ExpressionTarget::from(2),
+                    )))),
+                ),
+                Operator::Multiply,
+                ExpressionTarget::Function(Function::Pi, None),
+            )),
+        );
+        let (_, result) = parse_expression(input).unwrap();
+        assert_eq!(ExpressionTarget::from(expected), result);
+    }
+
+    #[test]
+    fn it_does_not_mistake_variables_for_functions() {
+        let (_, result) = parse_expression("INTEREST").unwrap();
+        assert_eq!(ExpressionTarget::Variable(String::from("INTEREST")), result);
+    }
+
+    #[test]
+    fn it_does_not_mistake_variables_for_not() {
+        let (_, result) = parse_expression("NOTE").unwrap();
+        assert_eq!(ExpressionTarget::Variable(String::from("NOTE")), result);
+
+        let (_, result) = parse_expression("NOT(a)").unwrap();
+        assert_eq!(
+            ExpressionTarget::Unary(
+                UnaryOperator::Not,
+                Box::new(ExpressionTarget::Variable(String::from("a")))
+            ),
+            result
+        );
+    }
+
+    #[test]
+    fn it_allows_spaces_inside_brackets() {
+        let (rest, result) = parse_expression("( a )").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(ExpressionTarget::Variable(String::from("a")), result);
+
+        let (rest, result) = parse_expression("a( 1 , 2 )").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            ExpressionTarget::Element(
+                String::from("a"),
+                vec![ExpressionTarget::from(1), ExpressionTarget::from(2)]
+            ),
+            result
+        );
+    }
+
+    #[test]
+    fn it_parses_string_function_names() {
+        let (_, result) = parse_expression("VAL$ a$").unwrap();
+        assert_eq!(
+            ExpressionTarget::Function(
+                Function::ValStr,
+                Some(Box::new(ExpressionTarget::Variable(String::from("a$"))))
+            ),
+            result
+        );
+    }
+
+    #[test]
+    fn it_parses_string_slices() {
+        let (_, result) = parse_expression("a$( TO TOTAL)").unwrap();
+        assert_eq!(
+            ExpressionTarget::Slice(
+                Box::new(ExpressionTarget::Variable(String::from("a$"))),
+                Box::new((
+                    None,
+                    Some(ExpressionTarget::Variable(String::from("TOTAL")))
+                ))
+            ),
+            result
+        );
+
+        let (_, result) = parse_expression("a$(2 TO )").unwrap();
+        assert_eq!(
+            ExpressionTarget::Slice(
+                Box::new(ExpressionTarget::Variable(String::from("a$"))),
+                Box::new((Some(ExpressionTarget::from(2)), None))
+            ),
+            result
+        );
+    }
 }
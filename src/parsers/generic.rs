@@ -2,7 +2,7 @@ use nom::{
     branch::alt,
     bytes::complete::{escaped_transform, tag, take_while},
     character::complete::none_of,
-    combinator::value,
+    combinator::{map, opt, value},
     sequence::delimited,
     IResult,
 };
@@ -12,13 +12,22 @@ pub fn consume_line(i: &str) -> IResult<&str, &str> {
     take_while(|c| c != '\n')(i)
 }
 
+// The reverse of read_string, for listing a program
+pub fn quote_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// escaped_transform fails on no input at all, so "" needs its own way through
 pub fn read_string(i: &str) -> IResult<&str, String> {
     delimited(
         tag("\""),
-        escaped_transform(
-            none_of("\\\""),
-            '\\',
-            alt((value("\\", tag("\\")), value("\"", tag("\"")))),
+        map(
+            opt(escaped_transform(
+                none_of("\\\""),
+                '\\',
+                alt((value("\\", tag("\\")), value("\"", tag("\"")))),
+            )),
+            Option::unwrap_or_default,
         ),
         tag("\""),
     )(i)
@@ -1,51 +1,78 @@
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{alphanumeric1, anychar, digit1, i64 as cci64},
+    character::complete::{alphanumeric1, anychar, digit1},
     combinator::{map, not, verify},
-    sequence::{preceded, terminated},
+    sequence::{pair, preceded, terminated},
     IResult,
 };
 
-use crate::commands::Primitive;
-
-use super::generic::{consume_line, read_string};
+use super::expressions::{
+    parse_expression, parse_slice, parse_subscripts, ExpressionTarget, Slice,
+};
 
 pub fn parse_int_variable_name(i: &str) -> IResult<&str, String> {
     map(preceded(not(digit1), alphanumeric1), String::from)(i)
 }
 
-pub fn parse_int(i: &str) -> IResult<&str, (String, Primitive)> {
-    let (i, id) = parse_int_variable_name(i)?;
-    let (i, _) = tag("=")(i)?;
-    let (i, var) = map(cci64, Primitive::Int)(i)?;
-
-    Ok((i, (id, var)))
-}
-
 pub fn parse_str_variable_name(i: &str) -> IResult<&str, String> {
     let (i, id) = terminated(verify(anychar, |c| c.is_alphabetic()), tag("$"))(i)?;
     let id = format!("{}$", id);
     Ok((i, id))
 }
 
-pub fn parse_str(i: &str) -> IResult<&str, (String, Primitive)> {
-    let (i, id) = parse_str_variable_name(i)?;
+// FOR loops may only be controlled by single letter numeric variables
+pub fn parse_control_variable_name(i: &str) -> IResult<&str, String> {
+    let (i, id) = terminated(
+        verify(anychar, |c| c.is_alphabetic()),
+        not(verify(anychar, |c| c.is_alphanumeric() || *c == '$')),
+    )(i)?;
+    Ok((i, id.to_string()))
+}
+
+// Arrays have single letter names, which may be followed by $ for strings
+pub fn parse_array_name(i: &str) -> IResult<&str, String> {
+    alt((parse_str_variable_name, parse_control_variable_name))(i)
+}
+
+pub fn parse_dim(i: &str) -> IResult<&str, (String, Vec<ExpressionTarget>)> {
+    pair(parse_array_name, parse_subscripts)(i)
+}
+
+pub fn parse_element_assignment(
+    i: &str,
+) -> IResult<&str, (String, Vec<ExpressionTarget>, ExpressionTarget)> {
+    let (i, (id, subscripts)) = pair(parse_array_name, parse_subscripts)(i)?;
     let (i, _) = tag("=")(i)?;
-    let (i, var) = map(read_string, Primitive::String)(i)?;
-    Ok((i, (id, var)))
+    let (i, value) = parse_expression(i)?;
+    Ok((i, (id, subscripts, value)))
 }
 
-pub fn parse_assignment(i: &str) -> IResult<&str, (String, Primitive)> {
-    let (i, id) = alt((parse_str_variable_name, parse_int_variable_name))(i)?;
+// Assigning to a slice overwrites part of a string without changing its length
+pub fn parse_slice_assignment(i: &str) -> IResult<&str, (String, Slice, ExpressionTarget)> {
+    let (i, (id, slice)) = pair(parse_str_variable_name, parse_slice)(i)?;
     let (i, _) = tag("=")(i)?;
-    let (i, assigned_variable) = consume_line(i)?;
-    Ok((
-        i,
-        (id, Primitive::Assignment(assigned_variable.to_string())),
-    ))
+    let (i, value) = parse_expression(i)?;
+    Ok((i, (id, slice, value)))
 }
 
-pub fn parse_var(i: &str) -> IResult<&str, (String, Primitive)> {
-    alt((parse_int, parse_str, parse_assignment))(i)
+// Anything that can be assigned to: a variable or an array element
+pub fn parse_target(i: &str) -> IResult<&str, ExpressionTarget> {
+    alt((
+        map(
+            pair(parse_array_name, parse_subscripts),
+            |(id, subscripts)| ExpressionTarget::Element(id, subscripts),
+        ),
+        map(
+            alt((parse_str_variable_name, parse_int_variable_name)),
+            ExpressionTarget::Variable,
+        ),
+    ))(i)
+}
+
+pub fn parse_var(i: &str) -> IResult<&str, (String, ExpressionTarget)> {
+    let (i, id) = alt((parse_str_variable_name, parse_int_variable_name))(i)?;
+    let (i, _) = tag("=")(i)?;
+    let (i, value) = parse_expression(i)?;
+    Ok((i, (id, value)))
 }
@@ -0,0 +1,158 @@
+use std::io::{self, BufRead, Write};
+
+use basic_interpreter::{
+    basic::{Dialect, Program, Report},
+    errors::RuntimeError,
+    parsers::commands::{parse_all, parse_line, parse_statements},
+};
+
+// Reads lines until the input runs out. A line with a number is added to the
+// program, a bare number deletes that line, and anything else is run at once.
+pub fn run(
+    dialect: Dialect,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    prompt: bool,
+) -> io::Result<()> {
+    let mut program = Program::default().with_dialect(dialect);
+    let mut count = 0;
+
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        let mut text = String::new();
+        if input.read_line(&mut text)? == 0 {
+            return Ok(());
+        }
+        count += 1;
+
+        let text = text.trim();
+        match text {
+            "" => (),
+            "RUN" => {
+                program.clear();
+                match program.check_blocks() {
+                    Ok(()) => {
+                        let result = program.execute_with(input, output);
+                        write_outcome(&program, result, output)?;
+                    }
+                    Err(error) => writeln!(output, "{}", error)?,
+                }
+            }
+            "LIST" => program.list(output)?,
+            "NEW" => program = Program::default().with_dialect(dialect),
+            "CLEAR" => program.clear(),
+            _ if text.starts_with(|c: char| c.is_ascii_digit()) => match text.parse() {
+                Ok(line) => program.delete_line(line),
+                Err(_) => match parse_all(count, text, parse_line) {
+                    Ok(line) => program.insert_line(line),
+                    Err(error) => writeln!(output, "{}", error)?,
+                },
+            },
+            _ => match parse_all(count, text, parse_statements) {
+                Ok(commands) => match program.check_blocks() {
+                    Ok(()) => {
+                        let result = program.execute_immediate(commands, input, output);
+                        write_outcome(&program, result, output)?;
+                    }
+                    Err(error) => writeln!(output, "{}", error)?,
+                },
+                Err(error) => writeln!(output, "{}", error)?,
+            },
+        }
+    }
+}
+
+// Reports go on a line of their own, even after a PRINT that ended with ; or ,
+fn write_outcome(
+    program: &Program,
+    result: Result<Report, RuntimeError>,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    if program.column() != 0 {
+        writeln!(output)?;
+    }
+    match result {
+        Ok(report) => writeln!(output, "{}", report),
+        Err(error) => writeln!(output, "{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use basic_interpreter::basic::Dialect;
+
+    use super::run;
+
+    fn session(dialect: Dialect, input: &str) -> String {
+        let mut output = Vec::new();
+        run(dialect, &mut input.as_bytes(), &mut output, false).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn it_runs_statements_immediately() {
+        let output = session(Dialect::Sinclair, "PRINT 2+2\nLET a=3: PRINT a*2\n");
+        assert_eq!(output, "4\n0 OK, 0:1\n6\n0 OK, 0:2\n");
+    }
+
+    #[test]
+    fn it_starts_reports_on_a_new_line() {
+        let input = "PRINT \"x\";\nPRINT TAB 3;\"y\"\n10 PRINT \"z\",\nRUN\n";
+        let output = session(Dialect::Sinclair, input);
+        assert_eq!(
+            output,
+            "x\n0 OK, 0:1\n   y\n0 OK, 0:1\nz               \n0 OK, 10:1\n"
+        );
+    }
+
+    #[test]
+    fn it_edits_and_runs_a_program() {
+        let input = "20 PRINT \"b\"\n10 PRINT \"a\"\n30 PRINT \"c\"\n20 PRINT \"B\"\nLIST\nRUN\n30\nLIST\nNEW\nLIST\n";
+        let output = session(Dialect::Sinclair, input);
+        assert_eq!(
+            output,
+            "10 PRINT \"a\"\n20 PRINT \"B\"\n30 PRINT \"c\"\na\nB\nc\n0 OK, 30:1\n10 PRINT \"a\"\n20 PRINT \"B\"\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_variables_until_cleared() {
+        let input = "10 LET a=5: STOP: PRINT a+1\nRUN\nPRINT a\nCONTINUE\nCLEAR\nPRINT a\nRUN\nINPUT n\n7\nPRINT n\n";
+        let output = session(Dialect::Sinclair, input);
+        assert_eq!(
+            output,
+            "9 STOP statement, 10:2\n5\n0 OK, 0:1\n6\n0 OK, 10:3\n2 Variable not found, 0:1\n9 STOP statement, 10:2\n0 OK, 0:1\n7\n0 OK, 0:1\n"
+        );
+    }
+
+    #[test]
+    fn it_reports_mistakes_without_losing_the_program() {
+        let input = "10 PRINT 1\n20 PRINT )\nPRINT (\nLIST\n";
+        let output = session(Dialect::Sinclair, input);
+        assert_eq!(
+            output,
+            "Syntax error at line 2, column 10:\n  20 PRINT )\n           ^\nSyntax error at line 3, column 7:\n  PRINT (\n        ^\n10 PRINT 1\n"
+        );
+    }
+
+    #[test]
+    fn it_matches_blocks_again_after_an_edit() {
+        let input = "10 LET i=0\n20 WHILE i<2\n30 LET i=i+1\n50 WEND\nRUN\n40 PRINT \"x\";i\n45 WEND\nGO TO 10\n45\nGO TO 10\n";
+        let output = session(Dialect::Structured, input);
+        assert_eq!(
+            output,
+            "0 OK, 20:1\nWEND without WHILE, 50:1\nx1\nx2\n0 OK, 20:1\n"
+        );
+    }
+
+    #[test]
+    fn it_checks_blocks_when_run() {
+        let input = "10 WHILE 0\nRUN\n20 WEND: STOP\nRUN\n";
+        let output = session(Dialect::Structured, input);
+        assert_eq!(output, "WHILE without WEND, 10:1\n9 STOP statement, 20:2\n");
+    }
+}